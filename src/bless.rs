@@ -0,0 +1,437 @@
+//! Rewriting annotations to match the compiler messages
+//!
+//! Stale annotations, the ones that didn't match any compiler message, are removed. Error and
+//! warning messages that didn't match any annotation get a new annotation, which is written:
+//!
+//! - after the annotations that already point to the same line, as a `//~|` shared annotation
+//! - at the end of the line the message points to, as an inline `//~` annotation, if that line
+//!   doesn't have a comment and the annotation fits in it
+//! - in a new line below the line the message points to, as a `//~^` adjusted annotation
+//!
+//! The `^`s of the adjusted annotations are recomputed to account for the lines that were added
+//! and removed, the rest of the source file is left untouched, line endings included.
+
+use std::cmp;
+use std::collections::BTreeMap;
+use std::iter;
+
+use unicode_width::UnicodeWidthStr;
+
+use {KINDS, BytePos, Kind, Line, Location, Message};
+use match_::Mismatches;
+use source::parse::{Parser, self};
+
+/// Inline annotations are not appended to a line if it would become wider than this
+const MAX_WIDTH: usize = 100;
+
+/// An annotation found in the source file
+struct Found {
+    location: Location,
+    /// Byte position of the character that follows the `~`, relative to the start of the line
+    marker: BytePos,
+    /// Is this a `//~|` shared annotation?
+    shared: bool,
+    /// Didn't match any compiler message
+    stale: bool,
+    /// Line the annotation points to
+    target: Line,
+}
+
+/// How a new annotation points to its line
+enum Marker {
+    /// `//~^`, adjusted to point to this line
+    Caret(Line),
+    /// `//~|`, shares the line with the preceding annotation
+    Or,
+}
+
+/// An annotation that will be written in its own line
+struct New {
+    indent: String,
+    marker: Marker,
+    /// `<kind> <message>`
+    body: String,
+}
+
+/// Rewrites the annotations of the `source` file to get rid of the `mismatches`
+///
+/// `source` must contain valid annotations, and `mismatches` must come from matching those
+/// annotations against the compiler messages.
+pub fn bless(source: &str, mismatches: &Mismatches) -> String {
+    let newline = if source.contains("\r\n") { "\r\n" } else { "\n" };
+    let lines: Vec<_> = source.lines().map(|line| line.trim_right_matches('\r')).collect();
+
+    let mut stale = vec![];
+    for &kind in &KINDS {
+        if let Some(mismatches) = mismatches.get(kind) {
+            for &(_, ref mismatch) in mismatches {
+                stale.extend(mismatch.annotations().iter().map(|ann| ann.location));
+            }
+        }
+    }
+
+    let mut found = vec![];
+    // NB the annotations of source files with revisions are never rewritten
+    for lka in Parser::new(source, &[]) {
+        // NB the source file has already been parsed without errors
+        let (target, _, annotation, _) = match lka {
+            Err(_) => break,
+            Ok(lka) => lka,
+        };
+        let location = annotation.location;
+        let line = lines[index(location.first)];
+        let marker = location.start + line[location.start..].find('~').unwrap() + "~".len();
+
+        found.push(Found {
+            location: location,
+            marker: marker,
+            shared: line[marker..].starts_with("|"),
+            stale: stale.contains(&location),
+            target: target,
+        });
+    }
+
+    // edits, indexed by line
+    let mut delete = vec![false; lines.len()];
+    let mut cut = vec![None; lines.len()];
+    let mut fix = vec![None; lines.len()];
+    let mut inline = vec![None; lines.len()];
+    let mut after: Vec<Vec<New>> = lines.iter().map(|_| vec![]).collect();
+
+    for (i, ann) in found.iter().enumerate() {
+        let first = index(ann.location.first);
+        let line = lines[first];
+
+        if ann.stale {
+            if line[..ann.location.start].trim().is_empty() {
+                delete[first] = true;
+            } else {
+                cut[first] = Some(ann.location.start);
+            }
+
+            for continuation in first + 1..index(ann.location.last) + 1 {
+                delete[continuation] = true;
+            }
+        } else if ann.shared {
+            // the preceding annotation is gone, point to the line with `^`s instead
+            if i > 0 && found[i - 1].stale {
+                fix[first] = Some((ann.marker, ann.marker + "|".len(), ann.target));
+            }
+        } else {
+            let carets = line[ann.marker..].bytes().take_while(|&b| b == b'^').count();
+
+            if carets > 0 {
+                fix[first] = Some((ann.marker, ann.marker + carets, ann.target));
+            }
+        }
+    }
+
+    let mut unmatched: BTreeMap<Line, Vec<String>> = BTreeMap::new();
+    for &kind in &[Kind::Error, Kind::Warning] {
+        if let Some(mismatches) = mismatches.get(kind) {
+            for &(line, ref mismatch) in mismatches {
+                for message in mismatch.messages() {
+                    let body = body(kind, message);
+
+                    if let Some(bodies) = unmatched.get_mut(&line) {
+                        bodies.push(body);
+                        continue
+                    }
+
+                    unmatched.insert(line, vec![body]);
+                }
+            }
+        }
+    }
+
+    for (target, bodies) in unmatched {
+        let t = index(target);
+
+        // the last annotation that points to the same line
+        let mut anchor: Option<&Found> = None;
+        for ann in found.iter().filter(|ann| !ann.stale && ann.target == target) {
+            if anchor.map_or(true, |anchor| ann.location.last > anchor.location.last) {
+                anchor = Some(ann);
+            }
+        }
+
+        match anchor {
+            Some(anchor) if anchor.location.first != target => {
+                let indent = indentation(lines[index(anchor.location.first)]);
+
+                after[index(anchor.location.last)].extend(bodies.into_iter().map(|body| {
+                    New { indent: indent.to_string(), marker: Marker::Or, body: body }
+                }));
+
+                continue
+            },
+            _ => {},
+        }
+
+        let code = match cut[t] {
+            Some(pos) => lines[t][..pos].trim_right(),
+            None => lines[t],
+        };
+
+        if anchor.is_none() && bodies.len() == 1 && !code.contains("//") {
+            let annotation = format!(" //~ {}", bodies[0]);
+
+            if UnicodeWidthStr::width(code) + UnicodeWidthStr::width(&*annotation) <= MAX_WIDTH {
+                inline[t] = Some(annotation);
+                continue
+            }
+        }
+
+        // insert the new annotations after the annotations that start in this line, and after
+        // the annotations that share a line with those
+        let mut end = t;
+        for ann in found.iter().filter(|ann| !ann.stale && index(ann.location.first) == t) {
+            end = cmp::max(end, index(ann.location.last));
+        }
+        loop {
+            let next = end + 1;
+
+            match found.iter().find(|ann| {
+                !ann.stale && ann.shared && index(ann.location.first) == next
+            }) {
+                Some(ann) => end = index(ann.location.last),
+                None => break,
+            }
+        }
+
+        let indent = indentation(lines[t]);
+        for (i, body) in bodies.into_iter().enumerate() {
+            let marker = if i == 0 { Marker::Caret(target) } else { Marker::Or };
+
+            after[end].push(New { indent: indent.to_string(), marker: marker, body: body });
+        }
+    }
+
+    // new source file, with its lines still pending to have their `^`s fixed
+    let mut blessed = vec![];
+    // index of each original line in the new source file
+    let mut moved_to = vec![None; lines.len()];
+
+    for (i, &line) in lines.iter().enumerate() {
+        if !delete[i] {
+            let mut text = match cut[i] {
+                Some(pos) => line[..pos].trim_right().to_string(),
+                None => line.to_string(),
+            };
+
+            if let Some(ref annotation) = inline[i] {
+                text.push_str(annotation);
+            }
+
+            moved_to[i] = Some(blessed.len());
+            blessed.push((text, fix[i]));
+        }
+
+        for new in &after[i] {
+            let prefix = format!("{}//~", new.indent);
+            let (marker, fix) = match new.marker {
+                Marker::Caret(target) => ("^", Some((prefix.len(), prefix.len() + 1, target))),
+                Marker::Or => ("|", None),
+            };
+
+            blessed.push((format!("{}{} {}", prefix, marker, new.body), fix));
+        }
+    }
+
+    let mut output = String::with_capacity(source.len());
+    for (i, (text, fix)) in blessed.into_iter().enumerate() {
+        let fix = fix.and_then(|(start, end, target)| {
+            moved_to[index(target)].map(|target| (start, end, target))
+        });
+
+        match fix {
+            Some((start, end, target)) => {
+                let carets = iter::repeat('^').take(i - target).collect::<String>();
+
+                output.push_str(&text[..start]);
+                output.push_str(&carets);
+                output.push_str(&text[end..]);
+            },
+            None => output.push_str(&text),
+        }
+
+        output.push_str(newline);
+    }
+
+    if !source.ends_with("\n") {
+        let len = output.len() - newline.len();
+        output.truncate(len);
+    }
+
+    output
+}
+
+/// `<kind> <message>` part of the annotation that matches this compiler message
+///
+/// Only the first line of the message is used, which is enough to match the whole message. Text
+/// that would be read back as something else is escaped: an error code becomes a `/<regex>/`
+/// annotation, and a `/<regex>/` loses its trailing slashes.
+fn body(kind: Kind, message: &Message) -> String {
+    let kind = match kind {
+        Kind::Error => "ERROR",
+        Kind::Help => "HELP",
+        Kind::Note => "NOTE",
+        Kind::Warning => "WARNING",
+    };
+
+    let first = message.text.lines().next().unwrap_or("").trim();
+    let text = if first.is_empty() {
+        // an annotation that only contains the error code, if any
+        message.code.unwrap_or("").to_string()
+    } else if parse::is_error_code(first) {
+        format!("/{}/", first)
+    } else if first.len() >= 2 && first.starts_with("/") && first.ends_with("/") {
+        // still a substring of the message
+        match first.trim_right_matches('/') {
+            "" => "/".to_string(),
+            text => text.to_string(),
+        }
+    } else {
+        first.to_string()
+    };
+
+    if text.is_empty() {
+        kind.to_string()
+    } else {
+        format!("{} {}", kind, text)
+    }
+}
+
+/// Leading whitespace of this line
+fn indentation(line: &str) -> &str {
+    &line[..line.len() - line.trim_left().len()]
+}
+
+/// Index of this line in the `lines` vector
+fn index(line: Line) -> usize {
+    line.0 as usize - 1
+}
+
+#[cfg(test)]
+mod test {
+    use {Kind, Message};
+    use match_;
+
+    /// Blesses the `source` file against these compiler `messages`
+    fn bless(source: &str, messages: &[(u32, Kind, &'static str)]) -> String {
+        let messages: Vec<_> = messages.iter().map(|&(ln, kind, text)| {
+            (ln, kind, Message { code: None, column: None, last_column: None, text: text })
+        }).collect();
+        let (anns, msgs) = match_::test::maps(source, &messages);
+
+        super::bless(source, &match_::match_(anns, msgs))
+    }
+
+    #[test]
+    fn stale() {
+        let source = "\
+fn main() {
+    let _: i8 = 0u8; //~ ERROR mismatched types
+    0.count_zeros();
+    //~^ ERROR no method named `count_zeros`
+    //~| ERROR type annotations needed
+    let x;
+    //~^^^^ ERROR old message
+}
+";
+        let messages = [
+            (2, Kind::Error, "mismatched types\nexpected `i8`, found `u8`"),
+            (3, Kind::Error, "type annotations needed"),
+        ];
+
+        assert_eq!(bless(source, &messages), "\
+fn main() {
+    let _: i8 = 0u8; //~ ERROR mismatched types
+    0.count_zeros();
+    //~^ ERROR type annotations needed
+    let x;
+}
+");
+    }
+
+    #[test]
+    fn unmatched() {
+        let source = "\
+fn main() {
+    let _: i8 = 0u8; // not an annotation
+    0.count_zeros();
+    //~^ ERROR type annotations needed
+    let x = 0;
+    foo();
+    //~^^ ERROR stale
+}
+";
+        let messages = [
+            (2, Kind::Error, "mismatched types"),
+            (3, Kind::Error, "type annotations needed"),
+            (3, Kind::Error, "no method named `count_zeros`"),
+            (5, Kind::Warning, "unused variable: `x`"),
+            (5, Kind::Warning, "unused variable: `y`"),
+            (6, Kind::Error, "cannot find function `foo`"),
+            (6, Kind::Note, "not annotated"),
+        ];
+
+        assert_eq!(bless(source, &messages), "\
+fn main() {
+    let _: i8 = 0u8; // not an annotation
+    //~^ ERROR mismatched types
+    0.count_zeros();
+    //~^ ERROR type annotations needed
+    //~| ERROR no method named `count_zeros`
+    let x = 0;
+    //~^ WARNING unused variable: `x`
+    //~| WARNING unused variable: `y`
+    foo(); //~ ERROR cannot find function `foo`
+}
+");
+    }
+
+    #[test]
+    fn escape() {
+        let source = "\
+fn main() {
+    let a = 0;
+    let b = 1;
+    let c = 2;
+}
+";
+        let messages = [
+            (2, Kind::Error, "/mismatched/"),
+            (3, Kind::Error, "E0308"),
+            (4, Kind::Error, "//"),
+        ];
+        let blessed = bless(source, &messages);
+
+        assert_eq!(blessed, "\
+fn main() {
+    let a = 0; //~ ERROR /mismatched
+    let b = 1; //~ ERROR /E0308/
+    let c = 2; //~ ERROR /
+}
+");
+
+        // the blessed file expects the same messages
+        let messages: Vec<_> = messages.iter().map(|&(ln, kind, text)| {
+            (ln, kind, Message { code: None, column: None, last_column: None, text: text })
+        }).collect();
+        let (anns, msgs) = match_::test::maps(&blessed, &messages);
+
+        assert!(match_::match_(anns, msgs).unmatched().is_empty());
+    }
+
+    #[test]
+    fn crlf() {
+        let source = "fn main() {\r\n    let _: i8 = 0u8; //~ ERROR stale\r\n}\r\n";
+        let messages = [(2, Kind::Error, "mismatched types")];
+
+        assert_eq!(bless(source, &messages),
+                   "fn main() {\r\n    let _: i8 = 0u8; //~ ERROR mismatched types\r\n}\r\n");
+        assert_eq!(bless(source.trim_right(), &messages),
+                   "fn main() {\r\n    let _: i8 = 0u8; //~ ERROR mismatched types\r\n}");
+    }
+}
@@ -0,0 +1,168 @@
+//! Test discovery
+//!
+//! Each argument of `find` can be:
+//!
+//! - a source file, which is tested as is
+//! - a directory, which is searched recursively for `*.rs` files, skipping the `auxiliary`
+//!   directories and without following symlinks to directories
+//! - a glob pattern, e.g. `tests/*-fail/*.rs`, whose matches are handled like the two cases
+//!   above, except for the matches inside an `auxiliary` directory, which are skipped
+//!
+//! The source files are returned sorted and without duplicates, so the same arguments always
+//! produce the same list of tests.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use glob::{self, GlobError, PatternError};
+
+/// Errors
+#[derive(Debug)]
+pub enum Error {
+    /// Failed to read a match of a glob pattern
+    Glob(GlobError),
+    /// Failed to read this directory
+    Io(PathBuf, io::Error),
+    /// Malformed glob pattern
+    Pattern(String, PatternError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Glob(ref e) => {
+                write!(f, "{}", e)
+            },
+            Error::Io(ref path, ref e) => {
+                write!(f, "couldn't read {}: {}", path.display(), e)
+            },
+            Error::Pattern(ref pattern, ref e) => {
+                write!(f, "malformed glob pattern `{}`: {}", pattern, e)
+            },
+        }
+    }
+}
+
+/// Returns the source files in `paths`
+pub fn find<P>(paths: &[P]) -> Result<Vec<PathBuf>, Error> where P: AsRef<Path> {
+    let mut sources = vec![];
+
+    for path in paths {
+        let path = path.as_ref();
+
+        if !is_pattern(path) {
+            try!(add(path, &mut sources));
+            continue
+        }
+
+        let pattern = path.to_string_lossy();
+        let matches = match glob::glob(&pattern) {
+            Err(e) => return Err(Error::Pattern(pattern.into_owned(), e)),
+            Ok(matches) => matches,
+        };
+
+        for match_ in matches {
+            match match_ {
+                Err(e) => return Err(Error::Glob(e)),
+                Ok(path) => if !path.components().any(|c| c.as_os_str() == "auxiliary") {
+                    try!(add(&path, &mut sources));
+                },
+            }
+        }
+    }
+
+    sources.sort();
+    sources.dedup();
+
+    Ok(sources)
+}
+
+/// Is this `path` a glob pattern?
+pub fn is_pattern(path: &Path) -> bool {
+    path.to_string_lossy().contains(|c| c == '*' || c == '?' || c == '[')
+}
+
+/// Adds `path` to the `sources`, or the source files it contains if it's a directory
+///
+/// NB paths that don't exist are added anyway, the test will report the error
+fn add(path: &Path, sources: &mut Vec<PathBuf>) -> Result<(), Error> {
+    match fs::metadata(path) {
+        Ok(ref metadata) if metadata.is_dir() => walk(path, sources),
+        _ => {
+            sources.push(path.to_path_buf());
+            Ok(())
+        },
+    }
+}
+
+/// Collects the `*.rs` files in the `dir` directory and its subdirectories, except for the
+/// `auxiliary` ones
+///
+/// NB symlinks to directories are not followed, they could form a loop
+fn walk(dir: &Path, sources: &mut Vec<PathBuf>) -> Result<(), Error> {
+    let entries = match fs::read_dir(dir) {
+        Err(e) => return Err(Error::Io(dir.to_path_buf(), e)),
+        Ok(entries) => entries,
+    };
+
+    for entry in entries {
+        let path = match entry {
+            Err(e) => return Err(Error::Io(dir.to_path_buf(), e)),
+            Ok(entry) => entry.path(),
+        };
+
+        match fs::symlink_metadata(&path) {
+            Err(e) => return Err(Error::Io(path, e)),
+            Ok(ref metadata) if metadata.is_dir() => {
+                if path.file_name().map_or(true, |name| name != "auxiliary") {
+                    try!(walk(&path, sources));
+                }
+            },
+            Ok(_) => if path.extension().map_or(false, |ext| ext == "rs") {
+                sources.push(path);
+            },
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::{File, self};
+    use std::path::PathBuf;
+
+    use tempdir::TempDir;
+
+    #[test]
+    fn find() {
+        let temp_dir = TempDir::new("cfail").unwrap();
+        let root = temp_dir.path();
+
+        for dir in &["a/auxiliary", "a/b", "c"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in &["a/auxiliary/aux.rs", "a/b/z.rs", "a/b/z.stderr", "a/y.rs", "c/x.rs"] {
+            File::create(root.join(file)).unwrap();
+        }
+
+        // a symlink loop
+        #[cfg(unix)]
+        ::std::os::unix::fs::symlink(root.join("a"), root.join("a/b/loop")).unwrap();
+
+        let args = [
+            root.join("c/x.rs"),
+            root.join("a"),
+            root.join("[ac]/*.rs"),
+            root.join("a/*/*.rs"),
+        ];
+        let expected: Vec<PathBuf> = ["a/b/z.rs", "a/y.rs", "c/x.rs"].iter().map(|file| {
+            root.join(file)
+        }).collect();
+
+        assert_eq!(super::find(&args).unwrap(), expected);
+        assert!(super::find(&[root.join("[a")]).is_err());
+    }
+}
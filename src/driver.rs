@@ -1,95 +1,180 @@
 //! CLI tool
 
-use std::env::{VarError, self};
+use std::env;
 use std::fmt;
-use std::sync::mpsc;
+use std::fs;
+use std::path::{Path, PathBuf};
 
-use num_cpus;
-use threadpool::ThreadPool;
-
-use {Outcome, test};
+use Config;
+use discover;
+use report::Format;
+use rustc::Compiler;
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
+            Error::BlessMatrix => {
+                f.write_str("`--bless` can't be used with several compilers")
+            },
+            Error::Discover(ref e) => {
+                write!(f, "{}", e)
+            },
             Error::MalformedRustThreads => {
                 f.write_str("the `RUST_THREADS` variable must contain a positive integer")
             },
+            Error::MissingValue(ref flag) => {
+                write!(f, "the `{}` flag expects a value", flag)
+            },
             Error::NoArgs => {
-                f.write_str("expected at least one argument, got none")
+                f.write_str("expected at least one path to a source file or directory, got none")
+            },
+            Error::SysrootMatrix => {
+                f.write_str("`--sysroot` can't be used with several compilers")
+            },
+            Error::UnknownFlag(ref flag) => {
+                write!(f, "unknown flag `{}`", flag)
+            },
+            Error::UnknownFormat(ref format) => {
+                write!(f,
+                       "unknown format `{}`, expected `pretty`, `terse`, `json` or `tap`",
+                       format)
             },
         }
     }
 }
 
 enum Error {
+    /// `--bless` in matrix mode
+    BlessMatrix,
+    /// couldn't collect the source files to test
+    Discover(discover::Error),
     /// malformed `RUST_THREADS`
     MalformedRustThreads,
-    /// no arguments passed to `cfail`
+    /// a `--flag` that expects a value was passed without one
+    MissingValue(&'static str),
+    /// no paths passed to `cfail`
     NoArgs,
+    /// `--sysroot` in matrix mode, each compiler has its own sysroot
+    SysrootMatrix,
+    /// `cfail` doesn't know about this `--flag`
+    UnknownFlag(String),
+    /// `--format` was passed an unknown format
+    UnknownFormat(String),
 }
 
-fn num_cpus() -> Result<usize, Error> {
-    match env::var("RUST_THREADS") {
-        Ok(threads) => match threads.parse() {
-            Ok(threads) if threads > 0 => Ok(threads),
-            _ => Err(Error::MalformedRustThreads),
-        },
-        Err(VarError::NotPresent) => Ok(num_cpus::get()),
-        Err(_) => Err(Error::MalformedRustThreads),
+fn run() -> Result<(), Error> {
+    let mut args = vec![];
+    let mut blessing = false;
+    let mut filters = vec![];
+    let compiler = Compiler::from_env();
+    let mut config = match Config::from_env() {
+        Err(_) => return Err(Error::MalformedRustThreads),
+        Ok(config) => config,
+    };
+    let mut listing = false;
+    let mut rustcs = vec![];
+    let mut sysroot = None;
+
+    let mut args_os = env::args_os().skip(1);
+    while let Some(arg) = args_os.next() {
+        match arg.to_str() {
+            // all the arguments after `--` are filters
+            Some("--") => {
+                filters.extend(args_os.by_ref());
+                break
+            },
+            Some("--bless") => blessing = true,
+            Some("--exact") => {
+                config.exact(true);
+            },
+            Some("--format") => match args_os.next() {
+                Some(format) => match format.to_str() {
+                    Some("json") => {
+                        config.format(Format::Json);
+                    },
+                    Some("pretty") => {
+                        config.format(Format::Pretty);
+                    },
+                    Some("tap") => {
+                        config.format(Format::Tap);
+                    },
+                    Some("terse") => {
+                        config.format(Format::Terse);
+                    },
+                    _ => return Err(Error::UnknownFormat(format.to_string_lossy().into_owned())),
+                },
+                None => return Err(Error::MissingValue("--format")),
+            },
+            Some("--junit") => match args_os.next() {
+                Some(path) => {
+                    config.junit(&path);
+                },
+                None => return Err(Error::MissingValue("--junit")),
+            },
+            Some("--list") => listing = true,
+            Some("--rustc") => match args_os.next() {
+                Some(rustc) => rustcs.push(PathBuf::from(rustc)),
+                None => return Err(Error::MissingValue("--rustc")),
+            },
+            Some("--skip") => match args_os.next() {
+                Some(skip) => {
+                    config.skip(&skip.to_string_lossy());
+                },
+                None => return Err(Error::MissingValue("--skip")),
+            },
+            Some("--sysroot") => match args_os.next() {
+                Some(path) => sysroot = Some(PathBuf::from(path)),
+                None => return Err(Error::MissingValue("--sysroot")),
+            },
+            Some(flag) if flag.starts_with("--") => {
+                return Err(Error::UnknownFlag(flag.to_string()))
+            },
+            _ => args.push(arg),
+        }
     }
-}
 
-fn run() -> Result<(), Error> {
-    let args: Vec<_> = env::args_os().skip(1).collect();
-    let mut errors = 0;
-    let mut failed = 0;
-    let mut ignored = 0;
-    let mut passed = 0;
+    // arguments that are neither existing paths nor glob patterns are filters, like in libtest,
+    // the arguments after `--` are always filters, e.g. `cfail tests/ -- foo`
+    let (roots, guesses): (Vec<_>, Vec<_>) = args.into_iter().partition(|arg| {
+        fs::metadata(arg).is_ok() || discover::is_pattern(Path::new(arg))
+    });
 
-    if args.is_empty() {
+    if roots.is_empty() {
         return Err(Error::NoArgs);
     }
 
-    let ntests = args.len();
-    let pool = ThreadPool::new(try!(num_cpus()));
-    let (tx, rx) = mpsc::channel();
-
-    for path in args {
-        let tx = tx.clone();
-        pool.execute(move || {
-            let outcome = test(&path);
-
-            tx.send((path, outcome)).unwrap();
-        });
+    for filter in guesses.iter().chain(&filters) {
+        config.filter(&filter.to_string_lossy());
     }
 
-    for (path, outcome) in rx.iter().take(ntests) {
-        let path = path.to_string_lossy();
+    // several `--rustc` flags run the tests against each compiler, the matrix mode, where each
+    // compiler uses its own sysroot
+    if rustcs.len() > 1 {
+        if blessing {
+            return Err(Error::BlessMatrix);
+        }
 
-        match outcome {
-            Err(e) => {
-                errors += 1;
-                println!("{} ... ERROR\n{}", path, e);
-            },
-            Ok(Outcome::Failed(mismatches)) => {
-                failed += 1;
-                println!("{} ... FAILED\n{}", path, mismatches)
-            },
-            Ok(Outcome::Ignored) => {
-                ignored += 1;
-                println!("{} ... ignored", path);
-            }
-            Ok(Outcome::Passed) => {
-                passed += 1;
-                println!("{} ... ok", path);
-            },
+        if sysroot.is_some() {
+            return Err(Error::SysrootMatrix);
         }
+
+        config.compilers(rustcs.into_iter().map(|rustc| Compiler::new(rustc, None)).collect());
+    } else {
+        let rustc = rustcs.pop().unwrap_or(compiler.rustc);
+
+        config.compiler(Compiler::new(rustc, sysroot.or(compiler.sysroot)));
     }
 
-    println!("{} passed; {} failed; {} ignored; {} errored", passed, failed, ignored, errors);
+    config.bless(blessing);
+
+    let paths = match discover::find(&roots) {
+        Err(e) => return Err(Error::Discover(e)),
+        Ok(paths) => paths,
+    };
 
-    if failed > 0 || errors > 0 {
+    if listing {
+        println!("\n{} tests", ::list(&config, &paths));
+    } else if !::run(&config, &paths).success() {
         env::set_exit_status(1);
     }
 
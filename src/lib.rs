@@ -7,20 +7,37 @@
 #![feature(into_cow)]
 #![feature(slice_patterns)]
 
+extern crate glob;
 extern crate num_cpus;
+extern crate regex;
+extern crate rustc_serialize;
 extern crate tempdir;
 extern crate threadpool;
+extern crate time;
 extern crate unicode_width;
 
 use std::borrow::Cow;
 use std::collections::BTreeMap;
 use std::ops::{Add, Sub};
-use std::path::Path;
-use std::{env, fmt, io};
+use std::path::{Path, PathBuf};
+use std::process::Output;
+use std::sync::{Arc, mpsc};
+use std::{cmp, env, fmt, io};
 
+use regex::Regex;
+use threadpool::ThreadPool;
+
+use report::{Format, Reporter, Summary, junit};
+use rustc::{Compiler, Version};
+
+pub mod bless;
+pub mod discover;
 pub mod driver;
 pub mod match_;
+pub mod normalize;
+pub mod report;
 pub mod rustc;
+pub mod snapshot;
 pub mod source;
 
 /// Source file line number
@@ -81,34 +98,18 @@ pub type LineMap<T> = BTreeMap<Line, T>;
 /// Errors
 #[derive(Debug)]
 pub enum Error {
+    /// Failed to compile an auxiliary crate: its path and the compiler stderr
+    AuxBuild(String, Option<String>),
     /// IO error
     Io(io::Error),
+    /// `RUST_THREADS` doesn't contain a positive integer
+    MalformedRustThreads,
     /// Error parsing the source file
     ParseSource(String),
     /// Error parsing the compiler stderr
     ParseStderr(String),
     /// Source file successfully compiled
     SuccessfulCompilation,
-    /// Unsupported feature
-    Unsupported(Feature),
-}
-
-/// Unsupported `cfail` features
-#[derive(Debug)]
-pub enum Feature {
-    /// Auxiliar build
-    AuxBuild,
-    /// Error pattern
-    ErrorPattern,
-}
-
-impl fmt::Display for Feature {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            Feature::AuxBuild => f.write_str("auxiliar builds"),
-            Feature::ErrorPattern => f.write_str("error patterns"),
-        }
-    }
 }
 
 impl From<io::Error> for Error {
@@ -120,9 +121,18 @@ impl From<io::Error> for Error {
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
+            Error::AuxBuild(ref path, None) => {
+                write!(f, "couldn't compile auxiliary crate {}", path)
+            },
+            Error::AuxBuild(ref path, Some(ref stderr)) => {
+                write!(f, "couldn't compile auxiliary crate {}:\n{}", path, stderr)
+            },
             Error::Io(ref e) => {
                 write!(f, "{}", e)
             },
+            Error::MalformedRustThreads => {
+                f.write_str("the `RUST_THREADS` variable must contain a positive integer")
+            },
             Error::ParseSource(ref err) => {
                 f.write_str(err)
             },
@@ -132,9 +142,33 @@ impl fmt::Display for Error {
             Error::SuccessfulCompilation => {
                 write!(f, "compilation succeeded")
             },
-            Error::Unsupported(ref feature) => {
-                write!(f, "{} are not currently supported", feature)
-            }
+        }
+    }
+}
+
+/// What's expected from a source file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// `// build-pass`, the source file must compile
+    BuildPass,
+    /// `// check-pass`, the source file must type check, but it's not translated
+    CheckPass,
+    /// The source file must fail to compile, this is the default
+    CompileFail,
+    /// `// run-fail`, the source file must compile, and the produced binary must fail at runtime
+    RunFail,
+    /// `// run-pass`, the source file must compile, and the produced binary must exit successfully
+    RunPass,
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Mode::BuildPass => f.write_str("build-pass"),
+            Mode::CheckPass => f.write_str("check-pass"),
+            Mode::CompileFail => f.write_str("compile-fail"),
+            Mode::RunFail => f.write_str("run-fail"),
+            Mode::RunPass => f.write_str("run-pass"),
         }
     }
 }
@@ -177,16 +211,61 @@ impl Kind {
     }
 }
 
+/// A `cfail` annotation
+#[derive(Debug)]
+pub struct Annotation<'a> {
+    /// Expected error code, e.g. `E0308`
+    code: Option<&'a str>,
+    /// Expected start column of the compiler message span
+    column: Option<u32>,
+    /// Expected last column of the compiler message span
+    last_column: Option<u32>,
+    /// Where the annotation is written in the source file
+    location: Location,
+    message: Cow<'a, str>,
+    /// `message` is a `/<regex>/`
+    regex: Option<Regex>,
+}
+
+impl<'a> fmt::Display for Annotation<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{:?}", self.message));
+
+        if let Some(code) = self.code {
+            try!(write!(f, " [{}]", code));
+        }
+
+        match (self.column, self.last_column) {
+            (Some(column), Some(last)) => try!(write!(f, " @{}-{}", column, last)),
+            (Some(column), None) => try!(write!(f, " @{}", column)),
+            _ => {},
+        }
+
+        Ok(())
+    }
+}
+
+/// Where an annotation is written in the source file
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Location {
+    /// Line where the annotation starts
+    first: Line,
+    /// Last line of the annotation, multi-line annotations span several lines
+    last: Line,
+    /// Byte position of the `//` that starts the annotation, relative to the start of `first`
+    start: BytePos,
+}
+
 /// `cfail` annotations
 #[derive(Debug)]
-pub struct Annotations<'a>([Option<Vec<Cow<'a, str>>>; NKINDS]);
+pub struct Annotations<'a>([Option<Vec<Annotation<'a>>>; NKINDS]);
 
 impl<'a> Annotations<'a> {
     fn new() -> Annotations<'a> {
         Annotations([None, None, None, None])
     }
 
-    fn insert(&mut self, kind: Kind, annotation: Cow<'a, str>) {
+    fn insert(&mut self, kind: Kind, annotation: Annotation<'a>) {
         if let Some(ref mut anns) = self.0[kind as usize] {
             anns.push(annotation)
         } else {
@@ -194,21 +273,46 @@ impl<'a> Annotations<'a> {
         }
     }
 
-    fn take(&mut self, kind: Kind) -> Option<Vec<Cow<'a, str>>> {
+    fn take(&mut self, kind: Kind) -> Option<Vec<Annotation<'a>>> {
         self.0[kind as usize].take()
     }
 }
 
+/// A compiler message
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Message<'a> {
+    /// Error code, e.g. `E0308`
+    code: Option<&'a str>,
+    /// Start column of the span the message points to
+    column: Option<u32>,
+    /// Last column of the span the message points to, `None` if the span ends in another line or
+    /// if the compiler didn't report it
+    last_column: Option<u32>,
+    text: &'a str,
+}
+
+impl<'a> fmt::Display for Message<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{:?}", self.text));
+
+        if let Some(code) = self.code {
+            try!(write!(f, " [{}]", code));
+        }
+
+        Ok(())
+    }
+}
+
 /// Compiler messages
 #[derive(Debug)]
-pub struct Messages<'a>([Option<Vec<&'a str>>; NKINDS]);
+pub struct Messages<'a>([Option<Vec<Message<'a>>>; NKINDS]);
 
 impl<'a> Messages<'a> {
     fn new() -> Messages<'a> {
         Messages([None, None, None, None])
     }
 
-    fn insert(&mut self, kind: Kind, message: &'a str) {
+    fn insert(&mut self, kind: Kind, message: Message<'a>) {
         if let Some(ref mut msgs) = self.0[kind as usize] {
             msgs.push(message)
         } else {
@@ -216,63 +320,987 @@ impl<'a> Messages<'a> {
         }
     }
 
-    fn take(&mut self, kind: Kind) -> Option<Vec<&'a str>> {
+    fn take(&mut self, kind: Kind) -> Option<Vec<Message<'a>>> {
         self.0[kind as usize].take()
     }
 }
 
 /// The outcome of the `cfail` test
+#[derive(Debug)]
 pub enum Outcome {
+    /// The annotations of the source file were rewritten to match the compiler messages
+    Blessed,
     /// The test failed
-    Failed(String),
+    Failed(Failure),
     /// The test was ignored
     Ignored,
     /// The test passed
     Passed,
+    /// The source file has several revisions, these are the results of testing each revision,
+    /// along with how long it took in nanoseconds
+    Revisions(Vec<(String, Result<Outcome, Error>, u64)>),
 }
 
-/// Performs a compile fail test on a source file
+/// Why a test failed
+#[derive(Debug)]
+pub struct Failure {
+    /// The annotations and compiler messages that didn't match, also described in `text`
+    pub mismatches: Vec<match_::Unmatched>,
+    /// Human readable description of everything that went wrong: mismatches, snapshot diffs,
+    /// unmatched error patterns, etc.
+    pub text: String,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// Configuration of a test run, see `run`
+#[derive(Clone, Debug)]
+pub struct Config {
+    bless: bool,
+    compilers: Vec<Compiler>,
+    exact: bool,
+    filters: Vec<String>,
+    flags: Vec<String>,
+    format: Format,
+    junit: Option<PathBuf>,
+    library_paths: Vec<PathBuf>,
+    mode: Mode,
+    skips: Vec<String>,
+    threads: usize,
+}
+
+impl Config {
+    /// The default configuration: tests against the `rustc` in `PATH`, in `CompileFail` mode,
+    /// using one thread per CPU
+    pub fn new() -> Config {
+        Config {
+            bless: false,
+            compilers: vec![Compiler::new(PathBuf::from("rustc"), None)],
+            exact: false,
+            filters: vec![],
+            flags: vec![],
+            format: Format::Pretty,
+            junit: None,
+            library_paths: vec![],
+            mode: Mode::CompileFail,
+            skips: vec![],
+            threads: num_cpus::get(),
+        }
+    }
+
+    /// The default configuration, but with the compiler of `Compiler::from_env`, the library
+    /// paths in the colon separated `CFAIL_LIBRARY_PATH` environment variable, and the number of
+    /// threads in the `RUST_THREADS` environment variable
+    ///
+    /// Fails if `RUST_THREADS` is set but doesn't contain a positive integer.
+    pub fn from_env() -> Result<Config, Error> {
+        let mut config = Config::new();
+        config.compilers = vec![Compiler::from_env()];
+
+        let library_path = env::var("CFAIL_LIBRARY_PATH").unwrap_or(String::new());
+        config.library_paths = library_path.split(':').map(PathBuf::from).collect();
+
+        match env::var("RUST_THREADS") {
+            Ok(threads) => match threads.parse() {
+                Ok(threads) if threads > 0 => config.threads = threads,
+                _ => return Err(Error::MalformedRustThreads),
+            },
+            Err(env::VarError::NotPresent) => {},
+            Err(_) => return Err(Error::MalformedRustThreads),
+        }
+
+        Ok(config)
+    }
+
+    /// Rewrites the source files and snapshots instead of failing on mismatches, see `bless`
+    ///
+    /// Has no effect when testing against several compilers.
+    pub fn bless(&mut self, bless: bool) -> &mut Config {
+        self.bless = bless;
+        self
+    }
+
+    /// Tests against this compiler
+    pub fn compiler(&mut self, compiler: Compiler) -> &mut Config {
+        self.compilers = vec![compiler];
+        self
+    }
+
+    /// Tests against each of these compilers
+    ///
+    /// An empty list of `compilers` is ignored, the tests run against the previous compilers.
+    pub fn compilers(&mut self, compilers: Vec<Compiler>) -> &mut Config {
+        if !compilers.is_empty() {
+            self.compilers = compilers;
+        }
+
+        self
+    }
+
+    /// Filters and skips must match the whole path of the source file, instead of a part of it
+    pub fn exact(&mut self, exact: bool) -> &mut Config {
+        self.exact = exact;
+        self
+    }
+
+    /// Only tests the source files whose path contains `filter`
+    ///
+    /// If several filters are added, a source file has to match one of them.
+    pub fn filter(&mut self, filter: &str) -> &mut Config {
+        self.filters.push(filter.to_string());
+        self
+    }
+
+    /// Passes this extra `flag` to the compiler, before the `compile-flags` of the source file
+    pub fn flag(&mut self, flag: &str) -> &mut Config {
+        self.flags.push(flag.to_string());
+        self
+    }
+
+    /// Prints the test results in this `format`
+    pub fn format(&mut self, format: Format) -> &mut Config {
+        self.format = format;
+        self
+    }
+
+    /// Also writes the test results to `path`, as a JUnit XML report, see the `report::junit`
+    /// module
+    pub fn junit<P: ?Sized>(&mut self, path: &P) -> &mut Config where P: AsRef<Path> {
+        self.junit = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Adds a directory to the library search path (`-L`), relative paths are relative to the
+    /// current directory
+    pub fn library_path<P: ?Sized>(&mut self, path: &P) -> &mut Config where P: AsRef<Path> {
+        self.library_paths.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Mode of the source files that don't have a mode directive
+    pub fn mode(&mut self, mode: Mode) -> &mut Config {
+        self.mode = mode;
+        self
+    }
+
+    /// Doesn't test the source files whose path contains `skip`
+    pub fn skip(&mut self, skip: &str) -> &mut Config {
+        self.skips.push(skip.to_string());
+        self
+    }
+
+    /// Runs up to this number of tests in parallel
+    pub fn threads(&mut self, threads: usize) -> &mut Config {
+        self.threads = threads;
+        self
+    }
+
+    fn matches(&self, path: &Path) -> bool {
+        let path = path.to_string_lossy();
+
+        let matches = |pattern: &String| {
+            if self.exact {
+                *path == **pattern
+            } else {
+                path.contains(&pattern[..])
+            }
+        };
+
+        (self.filters.is_empty() || self.filters.iter().any(|filter| matches(filter))) &&
+            !self.skips.iter().any(|skip| matches(skip))
+    }
+}
+
+/// What the threads that run the tests report back, along with the index of the job, i.e. the
+/// order in which the test was submitted
+enum Event {
+    Started(usize),
+    /// The outcome, and how long the test took in nanoseconds
+    Finished(usize, Result<Outcome, Error>, u64),
+}
+
+/// Tests the source files in `paths` as specified by the `config`
 ///
-/// This function
+/// `discover::find` collects the source files of whole directories. The results are printed to
+/// stdout as the tests finish, in the `Format` of the `config`, and written to its JUnit report,
+/// if any. Returns the test counters, added up over all the compilers; failing to write the JUnit
+/// report is reported on stderr, before the summary, and counts as an error.
+pub fn run<P>(config: &Config, paths: &[P]) -> Summary where P: AsRef<Path> {
+    let matrix = config.compilers.len() > 1;
+    let mut printer = config.format.reporter(&config.compilers, config.bless && !matrix);
+
+    let path = match config.junit {
+        None => return run_with(config, paths, &mut [&mut *printer]),
+        Some(ref path) => path,
+    };
+
+    // NB the JUnit report is written first, so that a failure to write it is reported before
+    // the summary
+    let mut junit = junit::Report::new(path);
+    let mut summary = run_with(config, paths, &mut [&mut junit, &mut *printer]);
+
+    if junit.failed() {
+        summary.errors += 1;
+    }
+
+    summary
+}
+
+/// Like `run`, but hands the results to these `reporters` instead of printing them
 ///
-/// Note: this function should never panic, if it does that's a bug
-pub fn test<P: ?Sized>(source: &P) -> Result<Outcome, Error> where P: AsRef<Path> {
-    fn test_(path: &Path) -> Result<Outcome, Error> {
-        use source::Source;
-        use rustc;
+/// The `Format` and the JUnit report of the `config` are ignored.
+pub fn run_with<P>(
+    config: &Config,
+    paths: &[P],
+    reporters: &mut [&mut Reporter],
+) -> Summary where
+    P: AsRef<Path>,
+{
+    let compilers = &config.compilers;
+    let matrix = compilers.len() > 1;
+    let paths: Vec<_> = paths.iter().map(|path| path.as_ref().to_path_buf()).filter(|path| {
+        config.matches(path)
+    }).collect();
 
-        let source = try!(Source::open(&path));
-        if source.contains("// ignore-test") {
-            return Ok(Outcome::Ignored)
+    let ntests = paths.len() * compilers.len();
+    let shared = Arc::new(config.clone());
+    let pool = ThreadPool::new(cmp::max(config.threads, 1));
+    let (tx, rx) = mpsc::channel();
+
+    for (i, compiler) in compilers.iter().enumerate() {
+        // asked once per compiler, rather than once per test
+        let release = compiler.release();
+        let sysroot = compiler.sysroot();
+
+        for (j, path) in paths.iter().enumerate() {
+            let compiler = compiler.clone();
+            let config = shared.clone();
+            let job = i * paths.len() + j;
+            let path = path.clone();
+            let sysroot = sysroot.clone();
+            let tx = tx.clone();
+            pool.execute(move || {
+                let bless = config.bless && !matrix;
+
+                tx.send(Event::Started(job)).unwrap();
+
+                let start = time::precise_time_ns();
+                let sysroot = sysroot.as_ref().map(|sysroot| &**sysroot);
+                let outcome = test_(&path, &config, &compiler, &|| release, sysroot, bless);
+                let duration = time::precise_time_ns() - start;
+
+                tx.send(Event::Finished(job, outcome, duration)).unwrap();
+            });
         }
+    }
+
+    let name = |job: usize| {
+        let path = paths[job % paths.len()].display();
 
-        if source.contains("// aux-build") {
-            return Err(Error::Unsupported(Feature::AuxBuild))
+        if matrix {
+            format!("{} ({})", path, compilers[job / paths.len()].rustc.display())
+        } else {
+            path.to_string()
         }
+    };
+
+    let mut summaries: Vec<_> = compilers.iter().map(|_| Summary::default()).collect();
+
+    for reporter in reporters.iter_mut() {
+        reporter.on_start(ntests);
+    }
+
+    for event in rx.iter().take(2 * ntests) {
+        match event {
+            Event::Started(job) => {
+                let name = name(job);
+
+                for reporter in reporters.iter_mut() {
+                    reporter.on_test_start(job, &name);
+                }
+            },
+            Event::Finished(job, outcome, duration) => {
+                let name = name(job);
+
+                summaries[job / paths.len()].record(&outcome);
 
-        if source.contains("// error-pattern") {
-            return Err(Error::Unsupported(Feature::ErrorPattern))
+                for reporter in reporters.iter_mut() {
+                    reporter.on_result(job, &name, &outcome, duration);
+                }
+            },
         }
+    }
+
+    for reporter in reporters.iter_mut() {
+        reporter.on_finish(&summaries);
+    }
+
+    Summary::total(&summaries)
+}
 
-        let annotations = match source.parse() {
+/// Prints the source files in `paths` that pass the filters of the `config`, along with their
+/// mode and revisions, without compiling them
+///
+/// Returns the number of tests, each revision of a source file is a test.
+pub fn list<P>(config: &Config, paths: &[P]) -> usize where P: AsRef<Path> {
+    use source::Source;
+
+    let mut ntests = 0;
+    for path in paths.iter().map(|path| path.as_ref()).filter(|path| config.matches(path)) {
+        let source = match Source::open(path) {
+            Err(e) => {
+                println!("{}: {}", path.display(), e);
+                continue
+            },
+            Ok(source) => source,
+        };
+        let header = match source.header() {
             Err((span, e)) => {
-                return Err(Error::ParseSource(source::parse::format_error(path, &source, span, e)))
+                println!("{}", source::parse::format_error(path, &source, span, e));
+                continue
             },
-            Ok(annotations) => annotations,
+            Ok(header) => header,
         };
 
-        let library_path = env::var("CFAIL_LIBRARY_PATH").unwrap_or(String::new());
-        let output = try!(rustc::compile(&path, &library_path));
-        let messages = try!(output.parse());
+        let mode = header.mode.unwrap_or(config.mode);
+        let ignored = if header.ignore { " (ignored)" } else { "" };
+
+        if header.revisions.is_empty() {
+            println!("{}: {}{}", path.display(), mode, ignored);
+            ntests += 1;
+        } else {
+            for revision in &header.revisions {
+                println!("{} [{}]: {}{}", path.display(), revision, mode, ignored);
+                ntests += 1;
+            }
+        }
+    }
+
+    ntests
+}
+
+/// Performs a compile fail test on a source file
+///
+/// The test is configured by `Config::from_env`.
+///
+/// Note: this function should never panic, if it does that's a bug
+pub fn test<P: ?Sized>(source: &P) -> Result<Outcome, Error> where P: AsRef<Path> {
+    let config = try!(Config::from_env());
+
+    let compiler = &config.compilers[0];
+    let sysroot = compiler.sysroot();
+    let sysroot = sysroot.as_ref().map(|sysroot| &**sysroot);
+
+    test_(source.as_ref(), &config, compiler, &|| compiler.release(), sysroot, false)
+}
+
+/// Performs a compile fail test on a source file, but instead of failing on mismatches, rewrites
+/// the annotations of the source file to match the compiler messages
+///
+/// Stale annotations are removed and unmatched error and warning messages get new annotations,
+/// see the `bless` module for details. The annotations of source files that have revisions are
+/// never rewritten. Existing snapshots are overwritten with the actual output, missing ones are
+/// not created, see the `snapshot` module. Unmatched error patterns can't be blessed and still
+/// fail the test.
+pub fn bless<P: ?Sized>(source: &P) -> Result<Outcome, Error> where P: AsRef<Path> {
+    let config = try!(Config::from_env());
+
+    let compiler = &config.compilers[0];
+    let sysroot = compiler.sysroot();
+    let sysroot = sysroot.as_ref().map(|sysroot| &**sysroot);
+
+    test_(source.as_ref(), &config, compiler, &|| compiler.release(), sysroot, true)
+}
+
+/// Tests the source file with the `compiler`, whose `release` version and `sysroot` are only
+/// known if the compiler could be run
+///
+/// The `release` version is only looked up if the test has `min-rustc`/`max-rustc` directives.
+fn test_(
+    path: &Path,
+    config: &Config,
+    compiler: &Compiler,
+    release: &Fn() -> Option<Version>,
+    sysroot: Option<&Path>,
+    bless: bool,
+) -> Result<Outcome, Error> {
+    use source::Source;
+
+    let source = try!(Source::open(&path));
+    let header = match source.header() {
+        Err((span, e)) => {
+            return Err(Error::ParseSource(source::parse::format_error(path, &source, span, e)))
+        },
+        Ok(header) => header,
+    };
+
+    if header.ignore {
+        return Ok(Outcome::Ignored)
+    }
 
+    // NB if the compiler version is unknown, the test runs anyway
+    if header.min_rustc.is_some() || header.max_rustc.is_some() {
+        if let Some(version) = release() {
+            if !header.supports(&version) {
+                return Ok(Outcome::Ignored)
+            }
+        }
+    }
+
+    if header.revisions.is_empty() {
+        test_revision(path, &source, &header, config, compiler, sysroot, None, bless)
+    } else {
+        Ok(Outcome::Revisions(header.revisions.iter().map(|&revision| {
+            let start = time::precise_time_ns();
+            let outcome = test_revision(path,
+                                        &source,
+                                        &header,
+                                        config,
+                                        compiler,
+                                        sysroot,
+                                        Some(revision),
+                                        bless);
+            let duration = time::precise_time_ns() - start;
+
+            (revision.to_string(), outcome, duration)
+        }).collect()))
+    }
+}
+
+/// Tests the source file compiled under `revision`
+fn test_revision(
+    path: &Path,
+    source: &source::Source,
+    header: &source::header::Header,
+    config: &Config,
+    compiler: &Compiler,
+    sysroot: Option<&Path>,
+    revision: Option<&str>,
+    bless: bool,
+) -> Result<Outcome, Error> {
+    use std::fs::File;
+    use std::io::Write;
+
+    let annotations = match source.parse(&header.revisions, revision) {
+        Err((span, e)) => {
+            return Err(Error::ParseSource(source::parse::format_error(path, &source, span, e)))
+        },
+        Ok(annotations) => annotations,
+    };
+
+    let mode = header.mode.unwrap_or(config.mode);
+    let auxiliary = path.parent().unwrap_or(Path::new("")).join("auxiliary");
+    let aux_builds: Vec<_> = header.aux_builds.iter().map(|aux| {
+        auxiliary.join(aux)
+    }).collect();
+    let mut flags = config.flags.clone();
+    flags.extend(header.compile_flags.iter().cloned());
+    if let Some(revision) = revision {
+        flags.push("--cfg".to_string());
+        flags.push(revision.to_string());
+    }
+    let mut output = try!(rustc::compile(compiler,
+                                         &path,
+                                         &config.library_paths,
+                                         &aux_builds,
+                                         &flags,
+                                         mode));
+    let normalizer = output.normalizer(sysroot, &header.normalize_stderr);
+    output.normalize(&normalizer);
+    let messages = try!(output.parse());
+
+    let mut failure = String::new();
+    let mut mismatched = vec![];
+    let mut blessed = false;
+    match (mode, output.success()) {
+        (Mode::CompileFail, true) => return Err(Error::SuccessfulCompilation),
+        (Mode::CompileFail, false) => {},
+        (_, false) => failure.push_str(&format!("compilation failed\n{}", output.stderr())),
+        (Mode::RunFail, true) | (Mode::RunPass, true) => {
+            let run = try!(output.run());
+            failure.push_str(&check_run(header, mode, &run));
+
+            let stdout = normalizer.apply(&String::from_utf8_lossy(&run.stdout));
+            let stderr = normalizer.apply(&String::from_utf8_lossy(&run.stderr));
+            let snapshots = [
+                ("run.stdout", "test binary stdout", stdout),
+                ("run.stderr", "test binary stderr", stderr),
+            ];
+
+            for &(extension, what, ref actual) in &snapshots {
+                let snapshot = snapshot::path(path, revision, extension);
+
+                if try!(check_snapshot(&snapshot, what, actual, bless, &mut failure)) {
+                    blessed = true;
+                }
+            }
+        },
+        (Mode::BuildPass, true) | (Mode::CheckPass, true) => {},
+    }
+
+    let patterns = &header.error_patterns;
+    let unmatched = match_::match_patterns(patterns, output.stderr());
+    if !unmatched.is_empty() {
+        failure.push_str(&match_::format_patterns(&unmatched));
+    }
+
+    let snapshot = snapshot::path(path, revision, "stderr");
+    let stderr = output.stderr();
+    if try!(check_snapshot(&snapshot, "compiler stderr", stderr, bless, &mut failure)) {
+        blessed = true;
+    }
+
+    // like in compiletest, tests that only have error patterns don't check the messages
+    if patterns.is_empty() || !annotations.is_empty() {
         let mismatches = match_::match_(annotations, messages);
 
-        if mismatches.get(Kind::Error).is_none() && mismatches.get(Kind::Warning).is_none() {
-            Ok(Outcome::Passed)
-        } else {
-            Ok(Outcome::Failed(match_::format(mismatches)))
+        // NB the annotations of source files with revisions are never rewritten, their
+        // mismatches fail the test even in bless mode
+        if bless && revision.is_none() {
+            let contents = bless::bless(&source, &mismatches);
+
+            if contents != &**source {
+                try!(try!(File::create(path)).write_all(contents.as_bytes()));
+                blessed = true;
+            }
+        } else if mismatches.get(Kind::Error).is_some() ||
+            mismatches.get(Kind::Warning).is_some()
+        {
+            mismatched = mismatches.unmatched();
+            failure.push_str(&match_::format(mismatches));
+        }
+    }
+
+    if !failure.is_empty() {
+        Ok(Outcome::Failed(Failure { mismatches: mismatched, text: failure }))
+    } else if blessed {
+        Ok(Outcome::Blessed)
+    } else {
+        Ok(Outcome::Passed)
+    }
+}
+
+/// Checks the `actual` output against the `snapshot`, if the snapshot exists, and pushes the
+/// differences to `failure`
+///
+/// In bless mode, the snapshot is overwritten instead. Returns whether the snapshot was blessed.
+fn check_snapshot(
+    snapshot: &Path,
+    what: &str,
+    actual: &str,
+    bless: bool,
+    failure: &mut String,
+) -> Result<bool, Error> {
+    let expected = match try!(snapshot::read(snapshot)) {
+        None => return Ok(false),
+        Some(expected) => expected,
+    };
+
+    if bless {
+        if expected != actual {
+            try!(snapshot::write(snapshot, actual));
+
+            return Ok(true)
+        }
+    } else if let Some(diff) = snapshot::diff(&expected, actual) {
+        failure.push_str(&format!("{} doesn't match the {}\n{}", snapshot.display(), what, diff));
+    }
+
+    Ok(false)
+}
+
+/// Checks the exit status and the stderr of the test binary against the expectations of the
+/// `RunFail`/`RunPass` `mode`, returns the failures
+fn check_run(header: &source::header::Header, mode: Mode, run: &Output) -> String {
+    let mut failure = String::new();
+    let stderr = String::from_utf8_lossy(&run.stderr);
+
+    if mode == Mode::RunPass {
+        if !run.status.success() {
+            failure.push_str(&format!("the test binary exited with {}\n{}", run.status, stderr));
+        }
+
+        return failure
+    }
+
+    if run.status.success() {
+        failure.push_str("the test binary exited successfully\n");
+    } else if let Some(code) = header.exit_code {
+        if run.status.code() != Some(code) {
+            failure.push_str(&format!("the test binary exited with {}, expected exit code {}\n",
+                                      run.status,
+                                      code));
+        }
+    }
+
+    for panic in &header.exec_panics {
+        if !stderr.contains(panic) {
+            failure.push_str(&format!("panic message {:?} not found in the test binary stderr\n",
+                                      panic));
+        }
+    }
+
+    if !failure.is_empty() {
+        failure.push_str(&stderr);
+    }
+
+    failure
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs::{File, self};
+    use std::io::{Read, Write};
+    use std::path::Path;
+
+    use tempdir::TempDir;
+
+    use {Config, Error, Outcome, snapshot};
+
+    /// Writes the `files` to a new temporary directory
+    fn write(files: &[(&str, &str)]) -> TempDir {
+        let temp_dir = TempDir::new("cfail").unwrap();
+
+        for &(path, contents) in files {
+            let path = temp_dir.path().join(path);
+
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(&path).unwrap().write_all(contents.as_bytes()).unwrap();
+        }
+
+        temp_dir
+    }
+
+    /// Writes the `files` to a temporary directory, and tests the first one
+    fn test(files: &[(&str, &str)]) -> Result<Outcome, Error> {
+        let temp_dir = write(files);
+        let config = Config::new();
+        let path = temp_dir.path().join(files[0].0);
+
+        super::test_(&path, &config, &config.compilers[0], &|| None, None, false)
+    }
+
+    #[test]
+    fn aux_build() {
+        let aux = ("auxiliary/zero.rs", "pub const ZERO: u8 = 0;\n");
+        let main = ("main.rs", "\
+// aux-build:zero.rs
+extern crate zero;
+
+fn main() {
+    let _: i8 = zero::ZERO; //~ ERROR mismatched types
+}
+");
+
+        match test(&[main, aux]) {
+            Ok(Outcome::Passed) => {},
+            outcome => panic!("{:?}", outcome),
+        }
+
+        match test(&[main, ("auxiliary/zero.rs", "pub const ZERO: u8 = ();\n")]) {
+            Err(Error::AuxBuild(ref path, Some(_))) if path.ends_with("zero.rs") => {},
+            outcome => panic!("{:?}", outcome),
         }
     }
 
-    test_(source.as_ref())
+    #[test]
+    fn error_patterns() {
+        let source = "\
+// error-pattern: mismatched types
+// error-pattern: expected `i8`, found `u8`
+fn main() {
+    let _: i8 = 0u8;
+}
+";
+
+        match test(&[("main.rs", source)]) {
+            Ok(Outcome::Passed) => {},
+            outcome => panic!("{:?}", outcome),
+        }
+
+        // the patterns must appear in order
+        let source = "\
+// error-pattern: expected `i8`, found `u8`
+// error-pattern: mismatched types
+fn main() {
+    let _: i8 = 0u8;
+}
+";
+
+        match test(&[("main.rs", source)]) {
+            Ok(Outcome::Failed(ref failure)) => {
+                assert_eq!(failure.text, "unmatched error patterns\n \"mismatched types\"\n");
+            },
+            outcome => panic!("{:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn bless_snapshot() {
+        let source = "\
+fn main() {
+    let _: i8 = 0u8; //~ ERROR mismatched types
+}
+";
+        let temp_dir = write(&[("main.rs", source), ("main.stderr", "outdated\n")]);
+        let config = Config::new();
+        let path = temp_dir.path().join("main.rs");
+        let snapshot = temp_dir.path().join("main.stderr");
+
+        match super::test_(&path, &config, &config.compilers[0], &|| None, None, true) {
+            Ok(Outcome::Blessed) => {},
+            outcome => panic!("{:?}", outcome),
+        }
+        assert!(snapshot::read(&snapshot).unwrap().unwrap().contains("mismatched types"));
+
+        match super::test_(&path, &config, &config.compilers[0], &|| None, None, false) {
+            Ok(Outcome::Passed) => {},
+            outcome => panic!("{:?}", outcome),
+        }
+
+        // missing snapshots are not created
+        let temp_dir = write(&[("main.rs", source)]);
+        let path = temp_dir.path().join("main.rs");
+
+        match super::test_(&path, &config, &config.compilers[0], &|| None, None, true) {
+            Ok(Outcome::Passed) => {},
+            outcome => panic!("{:?}", outcome),
+        }
+        assert!(snapshot::read(&temp_dir.path().join("main.stderr")).unwrap().is_none());
+    }
+
+    #[test]
+    fn check_pass() {
+        let source = "\
+// check-pass
+fn main() {
+    let x = 0; //~ WARNING unused variable
+}
+";
+
+        match test(&[("main.rs", source)]) {
+            Ok(Outcome::Passed) => {},
+            outcome => panic!("{:?}", outcome),
+        }
+
+        let source = "\
+// check-pass
+fn main() {
+    let _: i8 = 0u8;
+}
+";
+
+        match test(&[("main.rs", source)]) {
+            Ok(Outcome::Failed(ref failure)) => {
+                assert!(failure.text.starts_with("compilation failed\n"));
+                assert!(failure.text.contains("mismatched types"));
+            },
+            outcome => panic!("{:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn build_pass() {
+        // type checks, but doesn't link
+        let source = "\
+extern \"C\" {
+    fn cfail_undefined_symbol();
+}
+
+fn main() {
+    unsafe { cfail_undefined_symbol() }
+}
+";
+
+        match test(&[("main.rs", &format!("// check-pass\n{}", source))]) {
+            Ok(Outcome::Passed) => {},
+            outcome => panic!("{:?}", outcome),
+        }
+
+        match test(&[("main.rs", &format!("// build-pass\n{}", source))]) {
+            Ok(Outcome::Failed(ref failure)) => {
+                assert!(failure.text.starts_with("compilation failed\n"));
+                assert!(failure.text.contains("cfail_undefined_symbol"));
+            },
+            outcome => panic!("{:?}", outcome),
+        }
+
+        match test(&[("main.rs", "// build-pass\nfn main() {}\n")]) {
+            Ok(Outcome::Passed) => {},
+            outcome => panic!("{:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn run_pass() {
+        match test(&[("main.rs", "// run-pass\nfn main() {}\n")]) {
+            Ok(Outcome::Passed) => {},
+            outcome => panic!("{:?}", outcome),
+        }
+
+        match test(&[("main.rs", "// run-pass\nfn main() { panic!(\"boom\") }\n")]) {
+            Ok(Outcome::Failed(ref failure)) => {
+                assert!(failure.text.starts_with("the test binary exited with"));
+                assert!(failure.text.contains("boom"));
+            },
+            outcome => panic!("{:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn run_fail() {
+        let source = "\
+// run-fail
+// exec-panic: boom
+// exit-code: 101
+fn main() {
+    panic!(\"boom\")
+}
+";
+
+        match test(&[("main.rs", source)]) {
+            Ok(Outcome::Passed) => {},
+            outcome => panic!("{:?}", outcome),
+        }
+
+        match test(&[("main.rs", &source.replace("exec-panic: boom", "exec-panic: bang"))]) {
+            Ok(Outcome::Failed(ref failure)) => {
+                assert!(failure.text.starts_with("panic message \"bang\" not found"));
+            },
+            outcome => panic!("{:?}", outcome),
+        }
+
+        match test(&[("main.rs", &source.replace("exit-code: 101", "exit-code: 1"))]) {
+            Ok(Outcome::Failed(ref failure)) => {
+                assert!(failure.text.contains("expected exit code 1\n"));
+            },
+            outcome => panic!("{:?}", outcome),
+        }
+
+        match test(&[("main.rs", "// run-fail\nfn main() {}\n")]) {
+            Ok(Outcome::Failed(ref failure)) => {
+                assert_eq!(failure.text, "the test binary exited successfully\n");
+            },
+            outcome => panic!("{:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn compilers() {
+        let mut config = Config::new();
+        config.compilers(vec![]);
+
+        assert_eq!(config.compilers.len(), 1);
+        assert_eq!(config.compilers[0].rustc, Path::new("rustc"));
+    }
+
+    #[test]
+    fn from_env() {
+        env::set_var("RUST_THREADS", "3");
+        assert_eq!(Config::from_env().unwrap().threads, 3);
+
+        for threads in &["0", "abc", ""] {
+            env::set_var("RUST_THREADS", threads);
+            match Config::from_env() {
+                Err(Error::MalformedRustThreads) => {},
+                result => panic!("{:?}", result.map(|config| config.threads)),
+            }
+        }
+
+        env::remove_var("RUST_THREADS");
+        assert_eq!(Config::from_env().unwrap().threads, Config::new().threads);
+    }
+
+    #[test]
+    fn matches() {
+        let path = Path::new("tests/cfail/mismatched-types.rs");
+
+        let mut config = Config::new();
+        assert!(config.matches(path));
+
+        config.filter("mismatched").filter("unused");
+        assert!(config.matches(path));
+        assert!(!config.matches(Path::new("tests/cfail/borrowck.rs")));
+
+        config.skip("cfail/mis");
+        assert!(!config.matches(path));
+
+        // filters and skips must be the whole path
+        let mut config = Config::new();
+        config.exact(true).filter("mismatched-types.rs");
+        assert!(!config.matches(path));
+
+        config.filter("tests/cfail/mismatched-types.rs").skip("tests/cfail");
+        assert!(config.matches(path));
+
+        config.skip("tests/cfail/mismatched-types.rs");
+        assert!(!config.matches(path));
+    }
+
+    #[test]
+    fn bless_revisions() {
+        let source = "\
+// revisions: a b
+fn main() {
+    let _: i8 = 0u8; //[a,b]~ ERROR stale
+}
+";
+        let temp_dir = write(&[("main.rs", source)]);
+        let config = Config::new();
+        let path = temp_dir.path().join("main.rs");
+
+        match super::test_(&path, &config, &config.compilers[0], &|| None, None, true) {
+            Ok(Outcome::Revisions(ref revisions)) => {
+                assert_eq!(revisions.len(), 2);
+
+                for &(_, ref outcome, _) in revisions {
+                    match *outcome {
+                        Ok(Outcome::Failed(ref failure)) => {
+                            assert!(failure.text.contains("\"stale\""), "{}", failure.text);
+                        },
+                        ref outcome => panic!("{:?}", outcome),
+                    }
+                }
+            },
+            outcome => panic!("{:?}", outcome),
+        }
+
+        let mut contents = String::new();
+        File::open(&path).unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, source);
+    }
+
+    #[test]
+    fn run_snapshots() {
+        let source = "// run-pass\nfn main() { println!(\"hello\") }\n";
+
+        match test(&[("main.rs", source), ("main.run.stdout", "hello\n")]) {
+            Ok(Outcome::Passed) => {},
+            outcome => panic!("{:?}", outcome),
+        }
+
+        match test(&[("main.rs", source), ("main.run.stdout", "bye\n")]) {
+            Ok(Outcome::Failed(ref failure)) => {
+                assert!(failure.text.contains("main.run.stdout doesn't match the test binary \
+                                               stdout\n"));
+            },
+            outcome => panic!("{:?}", outcome),
+        }
+
+        let temp_dir = write(&[("main.rs", source), ("main.run.stdout", "bye\n")]);
+        let config = Config::new();
+        let path = temp_dir.path().join("main.rs");
+
+        match super::test_(&path, &config, &config.compilers[0], &|| None, None, true) {
+            Ok(Outcome::Blessed) => {},
+            outcome => panic!("{:?}", outcome),
+        }
+        let snapshot = temp_dir.path().join("main.run.stdout");
+        assert_eq!(snapshot::read(&snapshot).unwrap().unwrap(), "hello\n");
+    }
 }
@@ -1,10 +1,10 @@
 //! Matching annotations and messages
 
-use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::collections::BitVec;
+use std::fmt;
 
-use {KINDS, NKINDS, Annotations, Kind, Line, LineMap, Messages};
+use {KINDS, NKINDS, Annotation, Annotations, Kind, Line, LineMap, Message, Messages};
 
 /// Mismatches for every compiler message kind
 #[derive(Debug)]
@@ -20,6 +20,38 @@ impl<'a> Mismatches<'a> {
         self.0[kind as usize].as_ref().map(|v| &v[..])
     }
 
+    /// Returns an owned copy of the mismatches, see `Unmatched`
+    pub fn unmatched(&self) -> Vec<Unmatched> {
+        let mut unmatched = vec![];
+
+        for &kind in &KINDS {
+            if let Some(mismatches) = self.get(kind) {
+                for &(line, ref mismatch) in mismatches {
+                    unmatched.push(Unmatched {
+                        annotations: mismatch.annotations.iter().map(|ann| Entry {
+                            code: ann.code.map(|code| code.to_string()),
+                            column: ann.column,
+                            last_column: ann.last_column,
+                            regex: ann.regex.is_some(),
+                            text: ann.message.to_string(),
+                        }).collect(),
+                        kind: kind,
+                        line: line.0,
+                        messages: mismatch.messages.iter().map(|msg| Entry {
+                            code: msg.code.map(|code| code.to_string()),
+                            column: msg.column,
+                            last_column: msg.last_column,
+                            regex: false,
+                            text: msg.text.to_string(),
+                        }).collect(),
+                    });
+                }
+            }
+        }
+
+        unmatched
+    }
+
     fn insert(&mut self, kind: Kind, line: Line, mismatch: Mismatch<'a>) {
         if let Some(ref mut mismatches) = self.0[kind as usize] {
             mismatches.push((line, mismatch))
@@ -50,8 +82,69 @@ impl<'a> Mismatches<'a> {
 /// Mismatches per line
 #[derive(Debug)]
 pub struct Mismatch<'a> {
-    annotations: Vec<Cow<'a, str>>,
-    messages: Vec<&'a str>,
+    annotations: Vec<Annotation<'a>>,
+    messages: Vec<Message<'a>>,
+}
+
+impl<'a> Mismatch<'a> {
+    /// Annotations that didn't match any compiler message
+    pub fn annotations(&self) -> &[Annotation<'a>] {
+        &self.annotations
+    }
+
+    /// Compiler messages that didn't match any annotation
+    pub fn messages(&self) -> &[Message<'a>] {
+        &self.messages
+    }
+}
+
+/// The mismatches of one kind in one line
+///
+/// Unlike `Mismatch`, this doesn't borrow the source file or the compiler output.
+#[derive(Clone, Debug)]
+pub struct Unmatched {
+    /// Annotations that didn't match any compiler message
+    pub annotations: Vec<Entry>,
+    /// Kind of the annotations and messages
+    pub kind: Kind,
+    /// Line the annotations and messages point to
+    pub line: u32,
+    /// Compiler messages that didn't match any annotation
+    pub messages: Vec<Entry>,
+}
+
+/// An owned copy of an annotation or of a compiler message, see `Unmatched`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    /// Error code, e.g. `E0308`
+    pub code: Option<String>,
+    /// Start column of the span, expected by the annotation or reported by the compiler
+    pub column: Option<u32>,
+    /// Last column of the span, expected by the annotation or reported by the compiler
+    pub last_column: Option<u32>,
+    /// `text` is a `/<regex>/`, only annotations can be regexes
+    pub regex: bool,
+    /// Text of the annotation or of the compiler message
+    pub text: String,
+}
+
+/// Formatted like the annotations in `format`
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{:?}", self.text));
+
+        if let Some(ref code) = self.code {
+            try!(write!(f, " [{}]", code));
+        }
+
+        match (self.column, self.last_column) {
+            (Some(column), Some(last)) => try!(write!(f, " @{}-{}", column, last)),
+            (Some(column), None) => try!(write!(f, " @{}", column)),
+            _ => {},
+        }
+
+        Ok(())
+    }
 }
 
 /// Finds the mismatches between the `cfail` annotations and the compiler messages
@@ -95,8 +188,8 @@ pub fn match_<'a>(anns: LineMap<Annotations<'a>>, msgs: LineMap<Messages<'a>>) -
 }
 
 fn compare_opt<'a>(
-    anns: Option<Vec<Cow<'a, str>>>,
-    msgs: Option<Vec<&'a str>>,
+    anns: Option<Vec<Annotation<'a>>>,
+    msgs: Option<Vec<Message<'a>>>,
 ) -> Option<Mismatch<'a>> {
     match (anns, msgs) {
         (None, None) => None,
@@ -118,13 +211,13 @@ fn compare_opt<'a>(
     }
 }
 
-fn compare<'a>(anns: Vec<Cow<'a, str>>, msgs: Vec<&'a str>) -> Option<Mismatch<'a>> {
+fn compare<'a>(anns: Vec<Annotation<'a>>, msgs: Vec<Message<'a>>) -> Option<Mismatch<'a>> {
     let mut matched_anns = BitVec::from_elem(anns.len(), false);
     let mut matched_msgs = BitVec::from_elem(msgs.len(), false);
 
     for (i, ann) in anns.iter().enumerate() {
-        for (j, &msg) in msgs.iter().enumerate() {
-            if !matched_anns[i] && !matched_msgs[j] && is_substring(ann, msg) {
+        for (j, msg) in msgs.iter().enumerate() {
+            if !matched_anns[i] && !matched_msgs[j] && matches(ann, msg) {
                 matched_anns.set(i, true);
                 matched_msgs.set(j, true);
             }
@@ -164,23 +257,39 @@ pub fn format(mismatches: Mismatches) -> String {
                     buffer.push_str(&format!("{}: unmatched {} messages\n", line.0, kind));
 
                     for msg in &mismatched.messages {
-                        buffer.push_str(&format!(" {:?}\n", msg))
+                        buffer.push_str(&format!(" {}\n", msg))
                     }
                 } else if mismatched.messages.is_empty() {
                     buffer.push_str(&format!("{}: unmatched {} annotations\n", line.0, kind));
 
                     for ann in &mismatched.annotations {
-                        buffer.push_str(&format!(" {:?}\n", ann))
+                        buffer.push_str(&format!(" {}\n", ann))
                     }
                 } else {
                     buffer.push_str(&format!("{}: mismatched {} annotations\n", line.0, kind));
 
-                    for ann in &mismatched.annotations {
-                        buffer.push_str(&format!(" expected: {:?}\n", ann))
+                    // only show the columns of the messages if the annotations asked for them
+                    let anns = &mismatched.annotations;
+                    let columns = anns.iter().any(|ann| ann.column.is_some());
+                    let ranges = anns.iter().any(|ann| ann.last_column.is_some());
+
+                    for ann in anns {
+                        buffer.push_str(&format!(" expected: {}\n", ann))
                     }
 
                     for msg in &mismatched.messages {
-                        buffer.push_str(&format!("    found: {:?}\n", msg))
+                        match (msg.column, msg.last_column) {
+                            (Some(column), Some(last)) if ranges => {
+                                buffer.push_str(&format!("    found: {} @{}-{}\n",
+                                                         msg,
+                                                         column,
+                                                         last))
+                            },
+                            (Some(column), _) if columns => {
+                                buffer.push_str(&format!("    found: {} @{}\n", msg, column))
+                            },
+                            _ => buffer.push_str(&format!("    found: {}\n", msg)),
+                        }
                     }
                 }
             }
@@ -190,6 +299,51 @@ pub fn format(mismatches: Mismatches) -> String {
     buffer
 }
 
+/// Finds the error patterns that don't appear in the compiler output
+///
+/// Like in compiletest, the patterns must appear in order, each one in a line that comes after the
+/// line that matched the previous pattern.
+pub fn match_patterns<'a, 'b>(patterns: &'b [&'a str], output: &str) -> &'b [&'a str] {
+    let mut next = 0;
+
+    for line in output.lines() {
+        if next < patterns.len() && line.contains(patterns[next]) {
+            next += 1;
+        }
+    }
+
+    &patterns[next..]
+}
+
+/// Formats the error patterns that weren't found in the compiler output
+pub fn format_patterns(unmatched: &[&str]) -> String {
+    let mut buffer = String::from("unmatched error patterns\n");
+
+    for pattern in unmatched {
+        buffer.push_str(&format!(" {:?}\n", pattern))
+    }
+
+    buffer
+}
+
+/// Does the annotation match the compiler message?
+///
+/// The annotation must be a substring of the message, or if the annotation is a regex, the regex
+/// must match the message. If the annotation has an error code, the message must have the same
+/// code. If the annotation has a column, the message span must start at that column, and if it has
+/// a column range, the message span must also end at the last column of the range.
+fn matches(ann: &Annotation, msg: &Message) -> bool {
+    let is_match = match ann.regex {
+        Some(ref regex) => regex.is_match(msg.text),
+        None => is_substring(&ann.message, msg.text),
+    };
+
+    is_match &&
+        ann.code.map_or(true, |code| msg.code == Some(code)) &&
+        ann.column.map_or(true, |column| msg.column == Some(column)) &&
+        ann.last_column.map_or(true, |last| msg.last_column == Some(last))
+}
+
 /// Is the annotation a substring of the compiler message?
 fn is_substring(ann: &str, msg: &str) -> bool {
     let mut ann_lines = ann.lines().peekable();
@@ -208,8 +362,180 @@ fn is_substring(ann: &str, msg: &str) -> bool {
     ann_lines.count() == 0
 }
 
+/// Fixtures, also used by the tests of the `bless` module
 #[cfg(test)]
-mod test {
+pub mod test {
+    use std::collections::BTreeMap;
+
+    use {Annotations, Kind, Line, LineMap, Message, Messages};
+    use source::parse::Parser;
+
+    use super::{Entry, Unmatched};
+
+    /// Parses the annotations of the `source` file, and groups the compiler `messages` by line
+    pub fn maps<'a>(
+        source: &'a str,
+        messages: &[(u32, Kind, Message<'a>)],
+    ) -> (LineMap<Annotations<'a>>, LineMap<Messages<'a>>) {
+        let mut anns: LineMap<Annotations> = BTreeMap::new();
+        for lka in Parser::new(source, &[]) {
+            let (ln, kind, annotation, _) = lka.unwrap();
+
+            if !anns.contains_key(&ln) {
+                anns.insert(ln, Annotations::new());
+            }
+            anns.get_mut(&ln).unwrap().insert(kind, annotation);
+        }
+
+        let mut msgs: LineMap<Messages> = BTreeMap::new();
+        for &(ln, kind, message) in messages {
+            if !msgs.contains_key(&Line(ln)) {
+                msgs.insert(Line(ln), Messages::new());
+            }
+            msgs.get_mut(&Line(ln)).unwrap().insert(kind, message);
+        }
+
+        (anns, msgs)
+    }
+
+    /// Matches the annotations of the `source` file against the compiler `messages`
+    fn match_(source: &str, messages: &[(u32, Kind, Message<'static>)]) -> Vec<Unmatched> {
+        let (anns, msgs) = maps(source, messages);
+
+        super::match_(anns, msgs).unmatched()
+    }
+
+    /// An error message whose span covers the columns `column` to `last_column`
+    fn error(
+        line: u32,
+        column: u32,
+        last_column: Option<u32>,
+        text: &'static str,
+    ) -> (u32, Kind, Message<'static>) {
+        let message = Message {
+            code: None,
+            column: Some(column),
+            last_column: last_column,
+            text: text,
+        };
+
+        (line, Kind::Error, message)
+    }
+
+    /// An unmatched entry that isn't a regex
+    fn entry(text: &str, code: Option<&str>, column: Option<u32>, last: Option<u32>) -> Entry {
+        Entry {
+            code: code.map(|code| code.to_string()),
+            column: column,
+            last_column: last,
+            regex: false,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn columns() {
+        let source = "\
+let _: i8 = 0u8;
+//~^ ERROR@13 mismatched types
+//~| ERROR@13-15 expected `i8`
+";
+
+        let messages = [
+            error(1, 13, Some(15), "mismatched types"),
+            error(1, 13, Some(15), "expected `i8`, found `u8`"),
+        ];
+        assert!(match_(source, &messages).is_empty());
+
+        // the span ends one column too late
+        let messages = [
+            error(1, 13, Some(16), "mismatched types"),
+            error(1, 13, Some(16), "expected `i8`, found `u8`"),
+        ];
+        let unmatched = match_(source, &messages);
+        assert_eq!(unmatched.len(), 1);
+        assert_eq!(unmatched[0].annotations, [entry("expected `i8`", None, Some(13), Some(15))]);
+        assert_eq!(unmatched[0].annotations[0].to_string(), "\"expected `i8`\" @13-15");
+
+        // the end of the span is unknown, only the annotations without a range match
+        let messages = [
+            error(1, 13, None, "mismatched types"),
+            error(1, 13, None, "expected `i8`, found `u8`"),
+        ];
+        let annotations = &match_(source, &messages)[0].annotations;
+        assert_eq!(*annotations, [entry("expected `i8`", None, Some(13), Some(15))]);
+
+        // the span starts in another column
+        let messages = [
+            error(1, 12, Some(15), "mismatched types"),
+            error(1, 13, Some(15), "expected `i8`, found `u8`"),
+        ];
+        let annotations = &match_(source, &messages)[0].annotations;
+        assert_eq!(*annotations, [entry("mismatched types", None, Some(13), None)]);
+    }
+
+    #[test]
+    fn error_codes() {
+        let source = "\
+let _: i8 = 0u8; //~ ERROR E0308
+let _: i8 = 0u16; //~ ERROR[E0308] mismatched types
+";
+
+        let message = |line, code, text| {
+            (line, Kind::Error, Message {
+                code: code,
+                column: None,
+                last_column: None,
+                text: text,
+            })
+        };
+
+        let messages = [
+            message(1, Some("E0308"), "mismatched types"),
+            message(2, Some("E0308"), "mismatched types"),
+        ];
+        assert!(match_(source, &messages).is_empty());
+
+        // the code must match, and it's not part of the text
+        let messages = [
+            message(1, Some("E0309"), "mismatched types"),
+            message(2, None, "mismatched types [E0308]"),
+        ];
+        let unmatched = match_(source, &messages);
+        assert_eq!(unmatched.len(), 2);
+        let mismatched = |code| entry("mismatched types", Some(code), None, None);
+        assert_eq!(unmatched[0].annotations, [entry("", Some("E0308"), None, None)]);
+        assert_eq!(unmatched[0].messages, [mismatched("E0309")]);
+        assert_eq!(unmatched[1].annotations, [mismatched("E0308")]);
+        assert_eq!(unmatched[1].annotations[0].to_string(), "\"mismatched types\" [E0308]");
+    }
+
+    #[test]
+    fn regexes() {
+        let source = "\
+let _: i8 = 0u8; //~ ERROR /^mismatched types?$/
+let _: i8 = 0u16; //~ ERROR /expected `i\\d+`/
+";
+
+        let messages = [
+            error(1, 13, None, "mismatched types"),
+            error(2, 13, None, "expected `i8`, found `u16`"),
+        ];
+        assert!(match_(source, &messages).is_empty());
+
+        // the regex must match the message, the pattern is not a substring of it
+        let messages = [
+            error(1, 13, None, "mismatched types: expected `i8`"),
+            error(2, 13, None, "expected `u8`, found `u16`"),
+        ];
+        let unmatched = match_(source, &messages);
+        assert_eq!(unmatched.len(), 2);
+        assert_eq!(unmatched[0].annotations[0].text, "/^mismatched types?$/");
+        assert_eq!(unmatched[1].annotations[0].text, "/expected `i\\d+`/");
+        assert!(unmatched.iter().all(|unmatched| unmatched.annotations[0].regex));
+        assert!(unmatched.iter().all(|unmatched| !unmatched.messages[0].regex));
+    }
+
     #[test]
     fn is_substring() {
         let ann = "does not implement";
@@ -218,6 +544,20 @@ mod test {
         assert!(super::is_substring(ann, msg));
     }
 
+    #[test]
+    fn match_patterns() {
+        let patterns = ["mismatched types", "expected `i8`", "aborting"];
+        let output = "error: mismatched types\n expected `i8`, found `u8`\nerror: aborting";
+        assert!(super::match_patterns(&patterns, output).is_empty());
+
+        let output = "error: aborting\nerror: mismatched types\n expected `i8`, found `u8`";
+        assert_eq!(super::match_patterns(&patterns, output), &["aborting"]);
+
+        // a line matches at most one pattern
+        let output = "error: mismatched types, expected `i8`\nerror: aborting";
+        assert_eq!(super::match_patterns(&patterns, output), &["expected `i8`", "aborting"]);
+    }
+
     #[test]
     fn is_substring_multiline() {
         let ann = "mismatched types\nexpected `i8`\nfound `u8`";
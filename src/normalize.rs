@@ -0,0 +1,139 @@
+//! Compiler output normalization
+//!
+//! The compiler output contains paths that depend on the machine where the tests run. Before it's
+//! compared against snapshots or annotations, the output goes through these replacements, in
+//! order:
+//!
+//! - `\r\n` line endings become `\n`
+//! - the temporary directory where the test is compiled becomes `$TMP`
+//! - the directory that contains the rust source of the standard library becomes `$SRC_DIR`
+//! - the directory that contains the source file becomes `$DIR`
+//! - the `// normalize-stderr-test: "<regex>" -> "<replacement>"` rules of the source file, where
+//!   `<replacement>` can refer to the capture groups of `<regex>`, e.g. `$1`
+//!
+//! The directories are only replaced when they are followed by a path separator, e.g. if the
+//! source file is in `/src/tests`, `/src/tests-aux/a.rs` is left alone.
+
+use std::path::{Path, self};
+
+use regex::{NoExpand, Regex};
+
+/// A set of normalization rules
+pub struct Normalizer<'a> {
+    /// Built-in replacements of paths
+    paths: Vec<(String, &'static str)>,
+    /// `/rustc/<commit-hash>`, where the standard library sources are remapped to
+    remapped: Option<Regex>,
+    /// User-defined rules
+    rules: &'a [(Regex, String)],
+}
+
+impl<'a> Normalizer<'a> {
+    /// Creates the normalization rules for a source file located in `dir`, compiled in `temp_dir`
+    /// by a compiler with this `sysroot`
+    pub fn new(
+        dir: &Path,
+        temp_dir: &Path,
+        sysroot: Option<&Path>,
+        rules: &'a [(Regex, String)],
+    ) -> Normalizer<'a> {
+        let mut paths = vec![(temp_dir.to_string_lossy().into_owned(), "$TMP")];
+
+        if let Some(sysroot) = sysroot {
+            let src_dir = sysroot.join("lib/rustlib/src/rust");
+
+            paths.push((src_dir.to_string_lossy().into_owned(), "$SRC_DIR"));
+        }
+
+        paths.push((dir.to_string_lossy().into_owned(), "$DIR"));
+
+        Normalizer {
+            paths: paths,
+            remapped: Regex::new("/rustc/[0-9a-f]{40}").ok(),
+            rules: rules,
+        }
+    }
+
+    /// Normalizes the `text`
+    pub fn apply(&self, text: &str) -> String {
+        let mut text = text.replace("\r\n", "\n");
+
+        for &(ref path, placeholder) in &self.paths {
+            // NB an empty path would insert the placeholder between every character
+            if !path.is_empty() {
+                text = replace_dir(&text, path, placeholder);
+            }
+        }
+
+        if let Some(ref remapped) = self.remapped {
+            text = remapped.replace_all(&text, NoExpand("$SRC_DIR")).to_string();
+        }
+
+        for &(ref regex, ref replacement) in self.rules {
+            text = regex.replace_all(&text, &replacement[..]).to_string();
+        }
+
+        text
+    }
+}
+
+/// Replaces the occurrences of the directory `dir` in `text` that are followed by a path separator
+fn replace_dir(text: &str, dir: &str, placeholder: &str) -> String {
+    let mut replaced = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(i) = rest.find(dir) {
+        let after = &rest[i + dir.len()..];
+
+        replaced.push_str(&rest[..i]);
+        if after.chars().next().map_or(false, path::is_separator) {
+            replaced.push_str(placeholder);
+        } else {
+            replaced.push_str(dir);
+        }
+
+        rest = after;
+    }
+    replaced.push_str(rest);
+
+    replaced
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use regex::Regex;
+
+    use super::Normalizer;
+
+    #[test]
+    fn apply() {
+        let regex = Regex::new(r"\d+ previous errors").unwrap();
+        let rules = [(regex, "N previous errors".to_string())];
+        let normalizer = Normalizer::new(Path::new("/src/tests"),
+                                         Path::new("/src/tests/cfail.Ab12Cd"),
+                                         Some(Path::new("/toolchain")),
+                                         &rules);
+
+        let stderr = "\
+error: mismatched types\r
+ --> /src/tests/a.rs:1:1\r
+note: /src/tests/cfail.Ab12Cd/libaux.rlib\r
+ --> /src/tests-aux/b.rs:1:1\r
+ --> /toolchain/lib/rustlib/src/rust/library/core/src/lib.rs:1:1\r
+ --> /rustc/0123456789abcdef0123456789abcdef01234567/library/core/src/lib.rs:1:1\r
+error: aborting due to 2 previous errors\r
+";
+
+        assert_eq!(normalizer.apply(stderr), "\
+error: mismatched types
+ --> $DIR/a.rs:1:1
+note: $TMP/libaux.rlib
+ --> /src/tests-aux/b.rs:1:1
+ --> $SRC_DIR/library/core/src/lib.rs:1:1
+ --> $SRC_DIR/library/core/src/lib.rs:1:1
+error: aborting due to N previous errors
+");
+    }
+}
@@ -0,0 +1,219 @@
+//! JSON event stream
+//!
+//! One JSON object per line, with `type` and `event` fields:
+//!
+//! - `suite` `started`, with the number of `tests`
+//! - `test` `started`, with the `name` of the test
+//! - `test` `finished`, with the `name`, the `duration` (in seconds) and the `outcome` of the
+//!   test: `blessed`, `error`, `failed`, `ignored`, `ok` or `revisions`. Errors have an `error`
+//!   description; failures have a `failure` description and the `mismatches`, broken down by
+//!   `kind` and `line`, whose unmatched `annotations` and `messages` have a `text`, a `code`, a
+//!   `column`, a `last_column` (`null` if unknown) and whether they are a `regex`; and source
+//!   files with revisions have the `outcome` and the `duration` of each of their `revisions`
+//! - `suite` `finished`, with the counters of all the tests and whether the run was a `success`
+
+use std::collections::BTreeMap;
+
+use rustc_serialize::json::Json;
+
+use match_::Entry;
+use report::{Reporter, Summary};
+use {Error, Outcome};
+
+/// Prints the JSON event stream
+pub struct Stream;
+
+impl Reporter for Stream {
+    fn on_start(&mut self, ntests: usize) {
+        let mut object = event("suite", "started");
+        object.insert("tests".to_string(), Json::U64(ntests as u64));
+
+        println!("{}", Json::Object(object));
+    }
+
+    fn on_test_start(&mut self, _job: usize, name: &str) {
+        let mut object = event("test", "started");
+        object.insert("name".to_string(), Json::String(name.to_string()));
+
+        println!("{}", Json::Object(object));
+    }
+
+    fn on_result(
+        &mut self,
+        _job: usize,
+        name: &str,
+        outcome: &Result<Outcome, Error>,
+        duration: u64,
+    ) {
+        println!("{}", result_to_json(name, outcome, duration));
+    }
+
+    fn on_finish(&mut self, summaries: &[Summary]) {
+        let total = Summary::total(summaries);
+
+        let mut object = event("suite", "finished");
+        for &(key, value) in &[
+            ("blessed", total.blessed),
+            ("errors", total.errors),
+            ("failed", total.failed),
+            ("ignored", total.ignored),
+            ("passed", total.passed),
+        ] {
+            object.insert(key.to_string(), Json::U64(value as u64));
+        }
+        object.insert("success".to_string(), Json::Boolean(total.success()));
+
+        println!("{}", Json::Object(object));
+    }
+}
+
+/// A JSON object with these `type` and `event` fields
+fn event(type_: &str, event: &str) -> BTreeMap<String, Json> {
+    let mut object = BTreeMap::new();
+    object.insert("event".to_string(), Json::String(event.to_string()));
+    object.insert("type".to_string(), Json::String(type_.to_string()));
+
+    object
+}
+
+/// The `test` `finished` event of the test `name`, which took `duration` nanoseconds
+fn result_to_json(name: &str, outcome: &Result<Outcome, Error>, duration: u64) -> Json {
+    let mut object = outcome_to_json(outcome);
+    object.insert("duration".to_string(), seconds(duration));
+    object.insert("event".to_string(), Json::String("finished".to_string()));
+    object.insert("name".to_string(), Json::String(name.to_string()));
+    object.insert("type".to_string(), Json::String("test".to_string()));
+
+    Json::Object(object)
+}
+
+/// A duration in nanoseconds as seconds
+fn seconds(duration: u64) -> Json {
+    Json::F64(duration as f64 / 1e9)
+}
+
+/// Describes an unmatched annotation or compiler message as a JSON object
+fn entry_to_json(entry: &Entry) -> Json {
+    let number = |n: Option<u32>| n.map_or(Json::Null, |n| Json::U64(n as u64));
+
+    let mut object = BTreeMap::new();
+    object.insert("code".to_string(), entry.code.clone().map_or(Json::Null, Json::String));
+    object.insert("column".to_string(), number(entry.column));
+    object.insert("last_column".to_string(), number(entry.last_column));
+    object.insert("regex".to_string(), Json::Boolean(entry.regex));
+    object.insert("text".to_string(), Json::String(entry.text.clone()));
+
+    Json::Object(object)
+}
+
+/// Describes an `outcome` as a JSON object, whose `outcome` field is one of `blessed`, `error`,
+/// `failed`, `ignored`, `ok` or `revisions`
+fn outcome_to_json(outcome: &Result<Outcome, Error>) -> BTreeMap<String, Json> {
+    let mut object = BTreeMap::new();
+
+    let name = match *outcome {
+        Ok(Outcome::Blessed) => "blessed",
+        Err(ref e) => {
+            object.insert("error".to_string(), Json::String(e.to_string()));
+            "error"
+        },
+        Ok(Outcome::Failed(ref failure)) => {
+            let mismatches = failure.mismatches.iter().map(|unmatched| {
+                let entries = |entries: &[Entry]| {
+                    Json::Array(entries.iter().map(entry_to_json).collect())
+                };
+
+                let mut object = BTreeMap::new();
+                object.insert("annotations".to_string(), entries(&unmatched.annotations));
+                object.insert("kind".to_string(), Json::String(unmatched.kind.to_string()));
+                object.insert("line".to_string(), Json::U64(unmatched.line as u64));
+                object.insert("messages".to_string(), entries(&unmatched.messages));
+
+                Json::Object(object)
+            }).collect();
+
+            object.insert("failure".to_string(), Json::String(failure.text.clone()));
+            object.insert("mismatches".to_string(), Json::Array(mismatches));
+            "failed"
+        },
+        Ok(Outcome::Ignored) => "ignored",
+        Ok(Outcome::Passed) => "ok",
+        Ok(Outcome::Revisions(ref revisions)) => {
+            let revisions = revisions.iter().map(|&(ref revision, ref outcome, duration)| {
+                let mut object = outcome_to_json(outcome);
+                object.insert("duration".to_string(), seconds(duration));
+                object.insert("revision".to_string(), Json::String(revision.clone()));
+
+                Json::Object(object)
+            }).collect();
+
+            object.insert("revisions".to_string(), Json::Array(revisions));
+            "revisions"
+        },
+    };
+    object.insert("outcome".to_string(), Json::String(name.to_string()));
+
+    object
+}
+
+#[cfg(test)]
+mod test {
+    use rustc_serialize::json::Json;
+
+    use match_::{Entry, Unmatched};
+    use {Error, Failure, Kind, Outcome};
+
+    /// The string at this `key` of the JSON object
+    fn string<'a>(json: &'a Json, key: &str) -> Option<&'a str> {
+        json.find(key).and_then(|value| value.as_string())
+    }
+
+    #[test]
+    fn result_to_json() {
+        let failure = Failure {
+            mismatches: vec![Unmatched {
+                annotations: vec![Entry {
+                    code: Some("E0308".to_string()),
+                    column: Some(13),
+                    last_column: None,
+                    regex: false,
+                    text: "mismatched types".to_string(),
+                }],
+                kind: Kind::Error,
+                line: 2,
+                messages: vec![],
+            }],
+            text: "2: unmatched error annotations\n \"mismatched types\"\n".to_string(),
+        };
+        let json = super::result_to_json("a.rs", &Ok(Outcome::Failed(failure)), 1_500_000_000);
+
+        assert_eq!(string(&json, "type"), Some("test"));
+        assert_eq!(string(&json, "event"), Some("finished"));
+        assert_eq!(string(&json, "outcome"), Some("failed"));
+        assert_eq!(string(&json, "name"), Some("a.rs"));
+        assert_eq!(json.find("duration").and_then(|d| d.as_f64()), Some(1.5));
+        assert_eq!(json.find("mismatches").unwrap().to_string(),
+                   r#"[{"annotations":[{"code":"E0308","column":13,"#.to_string() +
+                   r#""last_column":null,"regex":false,"text":"mismatched types"}],"# +
+                   r#""kind":"error","line":2,"messages":[]}]"#);
+
+        let revisions = vec![
+            ("a".to_string(), Ok(Outcome::Passed), 500_000_000),
+            ("b".to_string(), Err(Error::SuccessfulCompilation), 0),
+        ];
+        let json = super::result_to_json("b.rs", &Ok(Outcome::Revisions(revisions)), 0);
+
+        assert_eq!(string(&json, "event"), Some("finished"));
+        assert_eq!(string(&json, "outcome"), Some("revisions"));
+        assert_eq!(json.find("revisions").unwrap().to_string(),
+                   r#"[{"duration":0.5,"outcome":"ok","revision":"a"},"#.to_string() +
+                   r#"{"duration":0.0,"error":"compilation succeeded","outcome":"error","# +
+                   r#""revision":"b"}]"#);
+
+        let json = super::result_to_json("c.rs", &Err(Error::SuccessfulCompilation), 0);
+
+        assert_eq!(string(&json, "event"), Some("finished"));
+        assert_eq!(string(&json, "outcome"), Some("error"));
+        assert_eq!(string(&json, "error"), Some("compilation succeeded"));
+    }
+}
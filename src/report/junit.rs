@@ -0,0 +1,261 @@
+//! JUnit XML reports
+//!
+//! Each source file is a `<testcase>`, or each of its revisions if it has several. Failures are
+//! reported as `<failure>`, errors as `<error>` and ignored tests as `<skipped>`.
+//!
+//! The report is collected by the `Report` reporter, and written to its file once all the tests
+//! have run, with the test cases in the order the tests were submitted. Failing to write it is
+//! reported on stderr.
+
+use std::fs::File;
+use std::io::{Write, self};
+use std::path::{Path, PathBuf};
+
+use time;
+
+use report::{Reporter, Summary};
+use {Error, Outcome};
+
+/// A JUnit report, to be written once all the tests have run
+pub struct Report {
+    /// `<testcase>` elements, and the job they belong to
+    cases: Vec<(usize, String)>,
+    errors: usize,
+    /// Writing the report failed
+    failed: bool,
+    failures: usize,
+    path: PathBuf,
+    skipped: usize,
+    /// When the test run started, in nanoseconds
+    start: u64,
+    tests: usize,
+    /// Wall time of the test run, in nanoseconds, the tests that run in parallel overlap
+    time: u64,
+}
+
+impl Report {
+    /// Creates an empty report that will be written to `path`
+    pub fn new(path: &Path) -> Report {
+        Report {
+            cases: vec![],
+            errors: 0,
+            failed: false,
+            failures: 0,
+            path: path.to_path_buf(),
+            skipped: 0,
+            start: 0,
+            tests: 0,
+            time: 0,
+        }
+    }
+
+    fn case(&mut self, job: usize, name: &str, outcome: &Result<Outcome, Error>, duration: u64) {
+        let body = match *outcome {
+            Ok(Outcome::Blessed) | Ok(Outcome::Passed) => None,
+            Err(ref e) => {
+                self.errors += 1;
+                Some(element("error", &e.to_string()))
+            },
+            Ok(Outcome::Failed(ref failure)) => {
+                self.failures += 1;
+                Some(element("failure", &failure.text))
+            },
+            Ok(Outcome::Ignored) => {
+                self.skipped += 1;
+                Some("<skipped/>".to_string())
+            },
+            Ok(Outcome::Revisions(ref revisions)) => {
+                for &(ref revision, ref outcome, duration) in revisions {
+                    self.case(job, &format!("{} [{}]", name, revision), outcome, duration)
+                }
+
+                return
+            },
+        };
+
+        self.tests += 1;
+
+        let mut case = format!("    <testcase classname=\"cfail\" name=\"{}\" time=\"{}\"",
+                               escape(name),
+                               seconds(duration));
+
+        match body {
+            None => case.push_str("/>\n"),
+            Some(body) => case.push_str(&format!(">\n      {}\n    </testcase>\n", body)),
+        }
+
+        self.cases.push((job, case));
+    }
+
+    /// Did writing the report to its file fail?
+    pub fn failed(&self) -> bool {
+        self.failed
+    }
+
+    /// Writes the report to its file
+    pub fn write(&self) -> io::Result<()> {
+        let mut file = try!(File::create(&self.path));
+
+        try!(file.write_all(b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites>\n"));
+        try!(write!(file,
+                    "  <testsuite name=\"cfail\" tests=\"{}\" failures=\"{}\" errors=\"{}\" \
+                     skipped=\"{}\" time=\"{}\">\n",
+                    self.tests,
+                    self.failures,
+                    self.errors,
+                    self.skipped,
+                    seconds(self.time)));
+
+        // NB the sort is stable, the revisions of a source file stay in order
+        let mut cases: Vec<_> = self.cases.iter().collect();
+        cases.sort_by(|a, b| a.0.cmp(&b.0));
+
+        for &&(_, ref case) in &cases {
+            try!(file.write_all(case.as_bytes()));
+        }
+
+        file.write_all(b"  </testsuite>\n</testsuites>\n")
+    }
+}
+
+impl Reporter for Report {
+    fn on_start(&mut self, _ntests: usize) {
+        self.start = time::precise_time_ns();
+    }
+
+    fn on_result(
+        &mut self,
+        job: usize,
+        name: &str,
+        outcome: &Result<Outcome, Error>,
+        duration: u64,
+    ) {
+        self.case(job, name, outcome, duration);
+    }
+
+    fn on_finish(&mut self, _summaries: &[Summary]) {
+        self.time = time::precise_time_ns() - self.start;
+
+        if let Err(e) = self.write() {
+            writeln!(io::stderr(),
+                     "error: couldn't write the JUnit report to {}: {}",
+                     self.path.display(),
+                     e).ok();
+            self.failed = true;
+        }
+    }
+}
+
+/// `<name message="<first line of text>">text</name>`
+fn element(name: &str, text: &str) -> String {
+    let message = text.lines().next().unwrap_or("");
+
+    format!("<{0} message=\"{1}\">{2}</{0}>", name, escape(message), escape(text))
+}
+
+/// Escapes the XML special characters of `text`, and drops the characters that XML doesn't allow
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c < ' ' => {},
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Formats a duration in nanoseconds as seconds
+fn seconds(duration: u64) -> String {
+    format!("{:.3}", duration as f64 / 1e9)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+    use std::io::Read;
+
+    use tempdir::TempDir;
+
+    use report::Reporter;
+    use {Error, Failure, Outcome};
+
+    use super::Report;
+
+    #[test]
+    fn report() {
+        let temp_dir = TempDir::new("cfail").unwrap();
+        let path = temp_dir.path().join("junit.xml");
+        let failure = || {
+            Failure { mismatches: vec![], text: "compilation failed\nerror: <T>".to_string() }
+        };
+
+        let mut report = Report::new(&path);
+        report.on_start(5);
+        // the results arrive in completion order, the cases are written in submission order
+        report.on_result(2, "c.rs", &Err(Error::SuccessfulCompilation), 3_000_000);
+        report.on_result(0, "a.rs", &Ok(Outcome::Passed), 1_000_000);
+        let revisions = vec![
+            ("x".to_string(), Ok(Outcome::Passed), 1_600_000),
+            ("y".to_string(), Ok(Outcome::Failed(failure())), 2_600_000),
+        ];
+        report.on_result(4, "e.rs", &Ok(Outcome::Revisions(revisions)), 4_000_000);
+        report.on_result(3, "d.rs", &Ok(Outcome::Ignored), 0);
+        report.on_result(1, "b.rs", &Ok(Outcome::Failed(failure())), 2_000_000);
+        report.on_finish(&[]);
+        assert!(!report.failed());
+
+        let mut xml = String::new();
+        File::open(&path).unwrap().read_to_string(&mut xml).unwrap();
+        let lines: Vec<_> = xml.lines().collect();
+
+        assert_eq!(lines[..2], ["<?xml version=\"1.0\" encoding=\"UTF-8\"?>", "<testsuites>"]);
+        assert!(lines[2].starts_with("  <testsuite name=\"cfail\" tests=\"6\" failures=\"2\" \
+                                      errors=\"1\" skipped=\"1\" time=\""));
+        assert_eq!(lines[3..], [
+            "    <testcase classname=\"cfail\" name=\"a.rs\" time=\"0.001\"/>",
+            "    <testcase classname=\"cfail\" name=\"b.rs\" time=\"0.002\">",
+            "      <failure message=\"compilation failed\">compilation failed",
+            "error: &lt;T&gt;</failure>",
+            "    </testcase>",
+            "    <testcase classname=\"cfail\" name=\"c.rs\" time=\"0.003\">",
+            "      <error message=\"compilation succeeded\">compilation succeeded</error>",
+            "    </testcase>",
+            "    <testcase classname=\"cfail\" name=\"d.rs\" time=\"0.000\">",
+            "      <skipped/>",
+            "    </testcase>",
+            "    <testcase classname=\"cfail\" name=\"e.rs [x]\" time=\"0.002\"/>",
+            "    <testcase classname=\"cfail\" name=\"e.rs [y]\" time=\"0.003\">",
+            "      <failure message=\"compilation failed\">compilation failed",
+            "error: &lt;T&gt;</failure>",
+            "    </testcase>",
+            "  </testsuite>",
+            "</testsuites>",
+        ]);
+    }
+
+    #[test]
+    fn unwritable() {
+        let temp_dir = TempDir::new("cfail").unwrap();
+
+        let mut report = Report::new(&temp_dir.path().join("missing/junit.xml"));
+        report.on_start(0);
+        report.on_finish(&[]);
+
+        assert!(report.failed());
+    }
+
+    #[test]
+    fn escape() {
+        assert_eq!(super::escape("expected `&'a T`, found <\"T\">\x1b"),
+                   "expected `&amp;&apos;a T`, found &lt;&quot;T&quot;&gt;");
+    }
+}
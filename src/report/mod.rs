@@ -0,0 +1,132 @@
+//! Reporting test results
+//!
+//! `run_with` hands the results of a test run to `Reporter`s. The built-in reporters print to
+//! stdout, in one of the output `Format`s, or write a JUnit XML report, see the `junit` module.
+
+use std::ops::Add;
+
+use rustc::Compiler;
+use {Error, Outcome};
+
+pub mod json;
+pub mod junit;
+pub mod pretty;
+pub mod tap;
+
+/// How the test results are printed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// One line per test, failures are printed as soon as they happen, this is the default
+    Pretty,
+    /// One JSON object per line, for each of these events: the test suite starts, the test of a
+    /// source file starts, the test of a source file ends, the test suite ends
+    Json,
+    /// Test Anything Protocol (version 13), the tests are numbered in the order they were
+    /// submitted, and the mismatches are reported as YAML blocks
+    Tap,
+    /// One character per test, failures are printed after all the tests have run
+    Terse,
+}
+
+impl Format {
+    /// Returns the reporter that prints in this format the results of testing with the
+    /// `compilers`
+    ///
+    /// The `Pretty` and `Terse` reporters include the number of blessed tests in their summary if
+    /// `bless` is set.
+    pub fn reporter(self, compilers: &[Compiler], bless: bool) -> Box<Reporter> {
+        match self {
+            Format::Json => Box::new(json::Stream),
+            Format::Pretty => Box::new(pretty::Pretty::new(compilers, bless)),
+            Format::Tap => Box::new(tap::Tap::new()),
+            Format::Terse => Box::new(pretty::Terse::new(compilers, bless)),
+        }
+    }
+}
+
+/// Receives the results of a test run, see `run_with`
+///
+/// The methods are called in this order: `on_start` once, then `on_test_start` and `on_result`
+/// once per source file and compiler, the tests that run in parallel may interleave, and finally
+/// `on_finish` once. `job` is the position of the test in the order the tests were submitted,
+/// starting at 0.
+pub trait Reporter {
+    /// The test run is about to start, `ntests` source files will be tested
+    fn on_start(&mut self, _ntests: usize) {}
+
+    /// The test of a source file has started
+    fn on_test_start(&mut self, _job: usize, _name: &str) {}
+
+    /// A source file has been tested, which took `duration` nanoseconds
+    ///
+    /// `name` is the path to the source file, followed by the compiler in parentheses when
+    /// testing against several compilers.
+    fn on_result(
+        &mut self,
+        job: usize,
+        name: &str,
+        outcome: &Result<Outcome, Error>,
+        duration: u64,
+    );
+
+    /// All the tests have run, `summaries` contains the counters of each compiler, in the order
+    /// of the `Config`
+    fn on_finish(&mut self, summaries: &[Summary]);
+}
+
+/// Test counters
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Summary {
+    /// Tests whose source file or snapshots were rewritten
+    pub blessed: usize,
+    /// Tests that couldn't be run
+    pub errors: usize,
+    /// Tests that failed
+    pub failed: usize,
+    /// Tests that were ignored
+    pub ignored: usize,
+    /// Tests that passed
+    pub passed: usize,
+}
+
+impl Add for Summary {
+    type Output = Summary;
+
+    fn add(self, rhs: Summary) -> Summary {
+        Summary {
+            blessed: self.blessed + rhs.blessed,
+            errors: self.errors + rhs.errors,
+            failed: self.failed + rhs.failed,
+            ignored: self.ignored + rhs.ignored,
+            passed: self.passed + rhs.passed,
+        }
+    }
+}
+
+impl Summary {
+    /// Adds up the counters of several summaries
+    pub fn total(summaries: &[Summary]) -> Summary {
+        summaries.iter().fold(Summary::default(), |total, &summary| total + summary)
+    }
+
+    /// Counts this `outcome`, each revision of a source file counts as a test
+    pub fn record(&mut self, outcome: &Result<Outcome, Error>) {
+        match *outcome {
+            Ok(Outcome::Blessed) => self.blessed += 1,
+            Err(_) => self.errors += 1,
+            Ok(Outcome::Failed(_)) => self.failed += 1,
+            Ok(Outcome::Ignored) => self.ignored += 1,
+            Ok(Outcome::Passed) => self.passed += 1,
+            Ok(Outcome::Revisions(ref revisions)) => {
+                for &(_, ref outcome, _) in revisions {
+                    self.record(outcome)
+                }
+            },
+        }
+    }
+
+    /// No test failed or errored
+    pub fn success(&self) -> bool {
+        self.failed == 0 && self.errors == 0
+    }
+}
@@ -0,0 +1,176 @@
+//! Human readable reporters
+
+use std::io::{Write, self};
+
+use report::{Reporter, Summary};
+use rustc::Compiler;
+use {Error, Outcome};
+
+/// One line per test, `<name> ... ok`, failures and errors are described right after their line
+pub struct Pretty {
+    bless: bool,
+    compilers: Vec<Compiler>,
+}
+
+impl Pretty {
+    /// Creates a reporter for the results of testing with the `compilers`, the summary includes
+    /// the number of blessed tests if `bless` is set
+    pub fn new(compilers: &[Compiler], bless: bool) -> Pretty {
+        Pretty {
+            bless: bless,
+            compilers: compilers.to_vec(),
+        }
+    }
+}
+
+impl Reporter for Pretty {
+    fn on_result(
+        &mut self,
+        _job: usize,
+        name: &str,
+        outcome: &Result<Outcome, Error>,
+        _duration: u64,
+    ) {
+        walk(name, outcome, &mut |name, status, _, details| {
+            match details {
+                None => println!("{} ... {}", name, status),
+                Some(details) => println!("{} ... {}\n{}", name, status, details),
+            }
+        });
+    }
+
+    fn on_finish(&mut self, summaries: &[Summary]) {
+        print_summaries(&self.compilers, summaries, self.bless);
+    }
+}
+
+/// One character per test, failures and errors are described after all the tests have run
+pub struct Terse {
+    bless: bool,
+    compilers: Vec<Compiler>,
+    failures: Vec<String>,
+}
+
+impl Terse {
+    /// Creates a reporter for the results of testing with the `compilers`, the summary includes
+    /// the number of blessed tests if `bless` is set
+    pub fn new(compilers: &[Compiler], bless: bool) -> Terse {
+        Terse {
+            bless: bless,
+            compilers: compilers.to_vec(),
+            failures: vec![],
+        }
+    }
+}
+
+impl Reporter for Terse {
+    fn on_result(
+        &mut self,
+        _job: usize,
+        name: &str,
+        outcome: &Result<Outcome, Error>,
+        _duration: u64,
+    ) {
+        let failures = &mut self.failures;
+
+        walk(name, outcome, &mut |name, status, c, details| {
+            print!("{}", c);
+
+            if let Some(details) = details {
+                failures.push(format!("{} ... {}\n{}", name, status, details));
+            }
+        });
+
+        io::stdout().flush().ok();
+    }
+
+    fn on_finish(&mut self, summaries: &[Summary]) {
+        println!("");
+
+        for failure in &self.failures {
+            println!("{}", failure);
+        }
+
+        print_summaries(&self.compilers, summaries, self.bless);
+    }
+}
+
+/// Calls `f` with the name, status, one character status and, for failures and errors, the
+/// details of each test in the `outcome`, a source file may contain several tests (revisions)
+fn walk<F>(name: &str, outcome: &Result<Outcome, Error>, f: &mut F) where
+    F: FnMut(&str, &str, char, Option<String>),
+{
+    let (status, c, details) = match *outcome {
+        Ok(Outcome::Blessed) => ("blessed", 'b', None),
+        Err(ref e) => ("ERROR", 'E', Some(e.to_string())),
+        Ok(Outcome::Failed(ref failure)) => ("FAILED", 'F', Some(failure.text.clone())),
+        Ok(Outcome::Ignored) => ("ignored", 'i', None),
+        Ok(Outcome::Passed) => ("ok", '.', None),
+        Ok(Outcome::Revisions(ref revisions)) => {
+            for &(ref revision, ref outcome, _) in revisions {
+                walk(&format!("{} [{}]", name, revision), outcome, f)
+            }
+
+            return
+        },
+    };
+
+    f(name, status, c, details)
+}
+
+/// Prints the `summaries` of the tests run against each of the `compilers`
+///
+/// A single compiler gets its version and the counters in one line, several compilers get a
+/// table with one row per compiler.
+fn print_summaries(compilers: &[Compiler], summaries: &[Summary], bless: bool) {
+    if compilers.len() > 1 {
+        println!("passed failed ignored errored toolchain");
+
+        for (compiler, summary) in compilers.iter().zip(summaries.iter()) {
+            println!("{:>6} {:>6} {:>7} {:>7} {}",
+                     summary.passed,
+                     summary.failed,
+                     summary.ignored,
+                     summary.errors,
+                     label(compiler));
+        }
+
+        return
+    }
+
+    let compiler = &compilers[0];
+    let summary = &summaries[0];
+
+    match compiler.version() {
+        Some(version) => print!("{}", version),
+        None => println!("{}: unknown version", compiler.rustc.display()),
+    }
+    if let Some(ref sysroot) = compiler.sysroot {
+        println!("sysroot: {}", sysroot.display());
+    }
+
+    if bless {
+        println!("{} passed; {} blessed; {} failed; {} ignored; {} errored",
+                 summary.passed,
+                 summary.blessed,
+                 summary.failed,
+                 summary.ignored,
+                 summary.errors);
+    } else {
+        println!("{} passed; {} failed; {} ignored; {} errored",
+                 summary.passed,
+                 summary.failed,
+                 summary.ignored,
+                 summary.errors);
+    }
+}
+
+/// Returns the first line of `rustc --version`, and the path to the compiler
+fn label(compiler: &Compiler) -> String {
+    match compiler.version() {
+        Some(version) => {
+            format!("{} ({})", version.lines().next().unwrap_or(""), compiler.rustc.display())
+        },
+        None => format!("unknown version ({})", compiler.rustc.display()),
+    }
+}
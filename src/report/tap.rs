@@ -0,0 +1,222 @@
+//! Test Anything Protocol (version 13)
+//!
+//! Each test (revision) gets an `ok`/`not ok` line, numbered in the order the tests were
+//! submitted. Ignored tests are marked `# SKIP`, and failures and errors are followed by a YAML
+//! block with their description and, for failures, the unmatched annotations and messages. `#`
+//! is escaped as `\#` in the test names.
+
+use std::collections::BTreeMap;
+
+use rustc_serialize::json::Json;
+
+use report::{Reporter, Summary};
+use {Error, Outcome};
+
+/// Prints the TAP stream
+pub struct Tap {
+    /// Index of the next job whose results will be printed
+    next: usize,
+    /// Results of the jobs that finished before the jobs submitted earlier
+    pending: BTreeMap<usize, Vec<Test>>,
+    /// Number of tests printed so far
+    tests: usize,
+}
+
+/// A TAP test line, along with its YAML block
+struct Test {
+    /// `# SKIP` or a description of the outcome
+    directive: &'static str,
+    name: String,
+    ok: bool,
+    yaml: Option<String>,
+}
+
+impl Tap {
+    /// Creates a reporter that hasn't printed any test yet
+    pub fn new() -> Tap {
+        Tap {
+            next: 0,
+            pending: BTreeMap::new(),
+            tests: 0,
+        }
+    }
+
+    /// Records the `outcome` of the `job`, and returns the lines of the tests that are ready to
+    /// be printed, i.e. those of the jobs submitted up to the first job that hasn't finished yet
+    fn result(&mut self, job: usize, name: &str, outcome: &Result<Outcome, Error>) -> String {
+        let mut tests = vec![];
+        collect(&escape(name), outcome, &mut tests);
+        self.pending.insert(job, tests);
+
+        let mut lines = String::new();
+        while let Some(tests) = self.pending.remove(&self.next) {
+            for test in tests {
+                self.tests += 1;
+                lines.push_str(&format!("{} {} - {}{}\n",
+                                        if test.ok { "ok" } else { "not ok" },
+                                        self.tests,
+                                        test.name,
+                                        test.directive));
+
+                if let Some(yaml) = test.yaml {
+                    lines.push_str(&format!("  ---\n{}  ...\n", yaml));
+                }
+            }
+
+            self.next += 1;
+        }
+
+        lines
+    }
+}
+
+impl Reporter for Tap {
+    fn on_start(&mut self, _ntests: usize) {
+        println!("TAP version 13");
+    }
+
+    fn on_result(
+        &mut self,
+        job: usize,
+        name: &str,
+        outcome: &Result<Outcome, Error>,
+        _duration: u64,
+    ) {
+        print!("{}", self.result(job, name, outcome));
+    }
+
+    fn on_finish(&mut self, summaries: &[Summary]) {
+        let total = Summary::total(summaries);
+
+        println!("1..{}", self.tests);
+        println!("# {} passed; {} failed; {} ignored; {} errored",
+                 total.passed,
+                 total.failed,
+                 total.ignored,
+                 total.errors);
+    }
+}
+
+/// Pushes the tests of this `outcome` to `tests`, a source file may contain several tests
+/// (revisions)
+fn collect(name: &str, outcome: &Result<Outcome, Error>, tests: &mut Vec<Test>) {
+    let (ok, directive, yaml) = match *outcome {
+        Ok(Outcome::Blessed) => (true, " (blessed)", None),
+        Err(ref e) => (false, "", Some(format!("  error: {}\n", quote(&e.to_string())))),
+        Ok(Outcome::Failed(ref failure)) => {
+            let mut yaml = format!("  failure: {}\n", quote(&failure.text));
+
+            if !failure.mismatches.is_empty() {
+                yaml.push_str("  mismatches:\n");
+            }
+
+            for unmatched in &failure.mismatches {
+                yaml.push_str(&format!("    - kind: {}\n      line: {}\n",
+                                       unmatched.kind,
+                                       unmatched.line));
+
+                for &(key, entries) in &[
+                    ("annotations", &unmatched.annotations),
+                    ("messages", &unmatched.messages),
+                ] {
+                    if entries.is_empty() {
+                        yaml.push_str(&format!("      {}: []\n", key));
+                    } else {
+                        yaml.push_str(&format!("      {}:\n", key));
+
+                        for entry in entries {
+                            yaml.push_str(&format!("        - {}\n", quote(&entry.to_string())));
+                        }
+                    }
+                }
+            }
+
+            (false, "", Some(yaml))
+        },
+        Ok(Outcome::Ignored) => (true, " # SKIP", None),
+        Ok(Outcome::Passed) => (true, "", None),
+        Ok(Outcome::Revisions(ref revisions)) => {
+            for &(ref revision, ref outcome, _) in revisions {
+                collect(&format!("{} [{}]", name, revision), outcome, tests)
+            }
+
+            return
+        },
+    };
+
+    tests.push(Test { directive: directive, name: name.to_string(), ok: ok, yaml: yaml });
+}
+
+/// Escapes `#` in a test `name`, which would otherwise start a directive, and the escape
+/// character `\` itself
+fn escape(name: &str) -> String {
+    name.replace("\\", "\\\\").replace("#", "\\#")
+}
+
+/// Quotes a string as a YAML double quoted scalar, which uses the same escapes as JSON
+fn quote(string: &str) -> String {
+    Json::String(string.to_string()).to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use match_::{Entry, Unmatched};
+    use {Error, Failure, Kind, Outcome};
+
+    use super::Tap;
+
+    #[test]
+    fn order() {
+        let mut tap = Tap::new();
+
+        assert_eq!(tap.result(2, "c.rs", &Ok(Outcome::Passed)), "");
+        assert_eq!(tap.result(0, "a.rs", &Ok(Outcome::Ignored)), "ok 1 - a.rs # SKIP\n");
+        assert_eq!(tap.result(1, "b.rs", &Ok(Outcome::Passed)), "ok 2 - b.rs\nok 3 - c.rs\n");
+    }
+
+    #[test]
+    fn yaml() {
+        let failure = Failure {
+            mismatches: vec![Unmatched {
+                annotations: vec![],
+                kind: Kind::Error,
+                line: 2,
+                messages: vec![Entry {
+                    code: None,
+                    column: None,
+                    last_column: None,
+                    regex: false,
+                    text: "mismatched types".to_string(),
+                }],
+            }],
+            text: "2: unmatched error messages\n \"mismatched types\"\n".to_string(),
+        };
+        let revisions = vec![
+            ("a".to_string(), Ok(Outcome::Failed(failure)), 0),
+            ("b".to_string(), Err(Error::SuccessfulCompilation), 0),
+        ];
+
+        assert_eq!(Tap::new().result(0, "a.rs", &Ok(Outcome::Revisions(revisions))), "\
+not ok 1 - a.rs [a]
+  ---
+  failure: \"2: unmatched error messages\\n \\\"mismatched types\\\"\\n\"
+  mismatches:
+    - kind: error
+      line: 2
+      annotations: []
+      messages:
+        - \"\\\"mismatched types\\\"\"
+  ...
+not ok 2 - a.rs [b]
+  ---
+  error: \"compilation succeeded\"
+  ...
+");
+    }
+
+    #[test]
+    fn escape() {
+        assert_eq!(Tap::new().result(0, "issue-#1\\a.rs", &Ok(Outcome::Passed)),
+                   "ok 1 - issue-\\#1\\\\a.rs\n");
+    }
+}
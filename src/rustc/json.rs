@@ -0,0 +1,344 @@
+//! `rustc` JSON diagnostics
+
+use rustc_serialize::json::Json;
+
+use {Error, Kind, Line};
+
+/// A compiler diagnostic, as emitted by `rustc --error-format=json`
+#[derive(Debug)]
+pub struct Diagnostic {
+    /// Sub-diagnostics, usually notes and helps attached to this diagnostic
+    pub children: Vec<Diagnostic>,
+    /// Error code, e.g. `E0308`
+    pub code: Option<String>,
+    /// The "kind" of this diagnostic
+    pub level: Kind,
+    /// The main message
+    pub message: String,
+    /// The diagnostic as it would have been rendered in the human readable format
+    pub rendered: Option<String>,
+    /// Source spans this diagnostic points to
+    pub spans: Vec<DiagnosticSpan>,
+}
+
+/// A source span attached to a diagnostic
+#[derive(Debug)]
+pub struct DiagnosticSpan {
+    /// Byte position where the span ends (exclusive)
+    pub byte_end: u32,
+    /// Byte position where the span starts
+    pub byte_start: u32,
+    /// Column where the span ends (exclusive, 1-based)
+    pub column_end: u32,
+    /// Column where the span starts (1-based)
+    pub column_start: u32,
+    /// If this span comes from a macro expansion, the span of the macro invocation
+    pub expansion: Option<Box<DiagnosticSpan>>,
+    /// Path to the file this span points to
+    pub file_name: String,
+    /// Is this the primary span of the diagnostic?
+    pub is_primary: bool,
+    /// Label attached to the span, e.g. "expected `i8`, found `u8`"
+    pub label: Option<String>,
+    /// Line where the span ends (1-based)
+    pub line_end: u32,
+    /// Line where the span starts (1-based)
+    pub line_start: u32,
+}
+
+/// A compiler message that points to a line of the source file
+#[derive(Debug, PartialEq)]
+pub struct Message {
+    /// Error code, e.g. `E0308`, or lint name
+    pub code: Option<String>,
+    /// Start column of the span
+    pub column: u32,
+    /// The "kind" of this message
+    pub kind: Kind,
+    /// Last column of the span, `None` if the span ends in another line
+    pub last_column: Option<u32>,
+    /// Line the span starts at
+    pub line: Line,
+    /// The message itself
+    pub text: String,
+}
+
+/// Parses the compiler stderr, which contains one JSON diagnostic per line
+///
+/// Lines that are not JSON objects (e.g. an ICE backtrace) are ignored.
+pub fn parse(stderr: &str) -> Result<Vec<Diagnostic>, Error> {
+    let mut diagnostics = vec![];
+
+    for line in stderr.lines() {
+        if !line.starts_with("{") {
+            continue
+        }
+
+        let json = match Json::from_str(line) {
+            Err(_) => return Err(Error::ParseStderr(line.to_string())),
+            Ok(json) => json,
+        };
+
+        // newer compilers also emit other kinds of messages, e.g. artifact notifications
+        match json.find("$message_type").and_then(|t| t.as_string()) {
+            Some("diagnostic") | None => {},
+            Some(_) => continue,
+        }
+
+        match Diagnostic::from_json(&json) {
+            None => return Err(Error::ParseStderr(line.to_string())),
+            Some(diagnostic) => diagnostics.push(diagnostic),
+        }
+    }
+
+    Ok(diagnostics)
+}
+
+/// Turns the compiler `stderr` back into the human readable format
+///
+/// Each JSON diagnostic is replaced by its `rendered` version, and the lines that are not JSON
+/// objects (e.g. an ICE backtrace or a linker error) are kept in place. Older compilers don't
+/// render the diagnostics, in that case the `stderr` is returned as it is.
+pub fn render(stderr: &str) -> String {
+    let mut human = String::new();
+    let mut rendered = false;
+
+    for line in stderr.lines() {
+        let json = if line.starts_with("{") { Json::from_str(line).ok() } else { None };
+        let json = match json {
+            None => {
+                human.push_str(line);
+                human.push('\n');
+                continue
+            },
+            Some(json) => json,
+        };
+
+        match json.find("$message_type").and_then(|t| t.as_string()) {
+            Some("diagnostic") | None => {},
+            Some(_) => continue,
+        }
+
+        if let Some(text) = json.find("rendered").and_then(|r| r.as_string()) {
+            human.push_str(text);
+            rendered = true;
+        }
+    }
+
+    if rendered { human } else { stderr.to_string() }
+}
+
+/// Flattens the diagnostics into compiler messages that point to lines of the `path` source file
+///
+/// - The line of a diagnostic is the line of its primary span. Spans that point into other files
+///   are traced back through their macro expansions.
+/// - Diagnostics that don't point to the source file, like the "aborting due to N previous
+///   errors" summary, are dropped.
+/// - Children without spans (the `= note: ...` trailers) inherit the location of their parent.
+pub fn messages(diagnostics: &[Diagnostic], path: &str) -> Vec<Message> {
+    let mut messages = vec![];
+
+    for diagnostic in diagnostics {
+        if let Some(location) = diagnostic.location(path) {
+            messages.push(diagnostic.message(location));
+
+            for child in &diagnostic.children {
+                messages.push(child.message(child.location(path).unwrap_or(location)));
+            }
+        }
+    }
+
+    messages
+}
+
+/// Line, start column and last column of a span
+type Location = (Line, u32, Option<u32>);
+
+impl Diagnostic {
+    fn from_json(json: &Json) -> Option<Diagnostic> {
+        let level = match json.find("level").and_then(|l| l.as_string()) {
+            Some("error") | Some("error: internal compiler error") => Kind::Error,
+            Some("help") => Kind::Help,
+            Some("note") | Some("failure-note") => Kind::Note,
+            Some("warning") => Kind::Warning,
+            _ => return None,
+        };
+
+        let message = match json.find("message").and_then(|m| m.as_string()) {
+            Some(message) => message.to_string(),
+            None => return None,
+        };
+
+        let code = json.find("code").and_then(|c| c.find("code")).and_then(|c| c.as_string());
+        let rendered = json.find("rendered").and_then(|r| r.as_string());
+
+        let mut spans = vec![];
+        if let Some(array) = json.find("spans").and_then(|s| s.as_array()) {
+            for span in array {
+                spans.push(try_opt!(DiagnosticSpan::from_json(span)));
+            }
+        }
+
+        let mut children = vec![];
+        if let Some(array) = json.find("children").and_then(|c| c.as_array()) {
+            for child in array {
+                children.push(try_opt!(Diagnostic::from_json(child)));
+            }
+        }
+
+        Some(Diagnostic {
+            children: children,
+            code: code.map(|c| c.to_string()),
+            level: level,
+            message: message,
+            rendered: rendered.map(|r| r.to_string()),
+            spans: spans,
+        })
+    }
+
+    /// Returns the location in the source file this diagnostic points to, if any
+    fn location(&self, path: &str) -> Option<Location> {
+        self.spans.iter().filter(|span| span.is_primary).filter_map(|span| {
+            span.origin(path)
+        }).next().map(|span| {
+            // NB `column_end` is exclusive
+            let last_column = if span.line_end != span.line_start {
+                None
+            } else if span.column_end > span.column_start {
+                Some(span.column_end - 1)
+            } else {
+                Some(span.column_start)
+            };
+
+            (Line(span.line_start), span.column_start, last_column)
+        })
+    }
+
+    /// Flattens the diagnostic into a compiler message. The text of the message is the message of
+    /// the diagnostic followed by the label of the primary span, in a new line.
+    fn message(&self, (line, column, last_column): Location) -> Message {
+        let mut text = self.message.clone();
+
+        if let Some(label) = self.spans.iter().filter(|span| span.is_primary).filter_map(|span| {
+            span.label.as_ref()
+        }).next() {
+            text.push('\n');
+            text.push_str(label);
+        }
+
+        Message {
+            code: self.code.clone(),
+            column: column,
+            kind: self.level,
+            last_column: last_column,
+            line: line,
+            text: text,
+        }
+    }
+}
+
+impl DiagnosticSpan {
+    fn from_json(json: &Json) -> Option<DiagnosticSpan> {
+        macro_rules! number {
+            ($field:expr) => {
+                try_opt!(json.find($field).and_then(|n| n.as_u64())) as u32
+            }
+        }
+
+        let file_name = try_opt!(json.find("file_name").and_then(|f| f.as_string()));
+        let is_primary = try_opt!(json.find("is_primary").and_then(|p| p.as_boolean()));
+        let label = json.find("label").and_then(|l| l.as_string());
+
+        let expansion = match json.find("expansion").and_then(|e| e.find("span")) {
+            Some(span) => Some(Box::new(try_opt!(DiagnosticSpan::from_json(span)))),
+            None => None,
+        };
+
+        Some(DiagnosticSpan {
+            byte_end: number!("byte_end"),
+            byte_start: number!("byte_start"),
+            column_end: number!("column_end"),
+            column_start: number!("column_start"),
+            expansion: expansion,
+            file_name: file_name.to_string(),
+            is_primary: is_primary,
+            label: label.map(|l| l.to_string()),
+            line_end: number!("line_end"),
+            line_start: number!("line_start"),
+        })
+    }
+
+    /// Follows the macro expansions until finding a span that points into the `path` file
+    fn origin(&self, path: &str) -> Option<&DiagnosticSpan> {
+        if self.file_name == path {
+            Some(self)
+        } else {
+            self.expansion.as_ref().and_then(|span| span.origin(path))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {Kind, Line};
+
+    use super::Message;
+
+    #[test]
+    fn messages() {
+        let stderr = concat!(
+            r#"{"message":"mismatched types","code":{"code":"E0308","explanation":null},"#,
+            r#""level":"error","spans":[{"file_name":"/src/a.rs","byte_start":24,"#,
+            r#""byte_end":27,"line_start":1,"line_end":1,"column_start":25,"column_end":28,"#,
+            r#""is_primary":true,"text":[],"label":"expected `i8`, found `u8`","#,
+            r#""suggested_replacement":null,"expansion":null}],"children":[{"#,
+            r#""message":"see the docs","code":null,"level":"note","spans":[],"children":[],"#,
+            r#""rendered":null}],"rendered":"error[E0308]: mismatched types\n"}"#, "\n",
+            r#"{"message":"aborting due to previous error","code":null,"level":"error","#,
+            r#""spans":[],"children":[],"rendered":"error: aborting due to previous error\n"}"#,
+        );
+
+        let diagnostics = super::parse(stderr).unwrap();
+        assert_eq!(diagnostics.len(), 2);
+
+        let messages = super::messages(&diagnostics, "/src/a.rs");
+        assert_eq!(messages, vec![
+            Message {
+                code: Some("E0308".to_string()),
+                column: 25,
+                kind: Kind::Error,
+                last_column: Some(27),
+                line: Line(1),
+                text: "mismatched types\nexpected `i8`, found `u8`".to_string(),
+            },
+            Message {
+                code: None,
+                column: 25,
+                kind: Kind::Note,
+                last_column: Some(27),
+                line: Line(1),
+                text: "see the docs".to_string(),
+            },
+        ]);
+    }
+
+    #[test]
+    fn render() {
+        let stderr = concat!(
+            r#"{"message":"mismatched types","rendered":"error: mismatched types\n"}"#, "\n",
+            "error: internal compiler error: unexpected panic\n",
+            r#"{"$message_type":"artifact","artifact":"a.rmeta","emit":"metadata"}"#, "\n",
+            r#"{"message":"aborting","rendered":"error: aborting\n"}"#, "\n",
+        );
+
+        assert_eq!(super::render(stderr), "\
+error: mismatched types
+error: internal compiler error: unexpected panic
+error: aborting
+");
+
+        // older compilers don't render the diagnostics
+        let stderr = r#"{"message":"mismatched types","code":null,"level":"error"}"#;
+        assert_eq!(super::render(stderr), stderr);
+    }
+}
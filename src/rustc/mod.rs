@@ -1,56 +1,370 @@
 //! The `rustc` compiler
 
 use std::collections::BTreeMap;
-use std::env;
-use std::path::Path;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::{env, fmt, io};
 
+use regex::Regex;
 use tempdir::TempDir;
 
-use {Error, LineMap, Messages};
+use normalize::Normalizer;
+use {Error, LineMap, Message, Messages, Mode};
 
 use self::parse::Parser;
 
+macro_rules! try_opt {
+    ($e:expr) => {
+        match $e {
+            Some(x) => x,
+            None => return None,
+        }
+    }
+}
+
+pub mod json;
 pub mod parse;
 
+/// The compiler used to compile the tests
+#[derive(Clone, Debug)]
+pub struct Compiler {
+    /// Path to the `rustc` executable
+    pub rustc: PathBuf,
+    /// Sysroot passed to the compiler, the compiler picks its own if `None`
+    pub sysroot: Option<PathBuf>,
+}
+
+impl Compiler {
+    /// Creates a compiler, relative paths are resolved against the current directory because the
+    /// tests are compiled in a temporary directory
+    ///
+    /// NB a bare `rustc` path, with a single component, is looked up in the `PATH` instead
+    pub fn new(rustc: PathBuf, sysroot: Option<PathBuf>) -> Compiler {
+        Compiler {
+            rustc: if rustc.components().count() > 1 { absolute(rustc) } else { rustc },
+            sysroot: sysroot.map(absolute),
+        }
+    }
+
+    /// The compiler in the `RUSTC` environment variable, or `rustc` if the variable is not set,
+    /// with the sysroot in the `CFAIL_SYSROOT` environment variable
+    pub fn from_env() -> Compiler {
+        Compiler::new(PathBuf::from(env::var("RUSTC").unwrap_or("rustc".to_string())),
+                      env::var("CFAIL_SYSROOT").ok().map(PathBuf::from))
+    }
+
+    /// Returns the output of `rustc --version --verbose`, if the compiler could be run
+    pub fn version(&self) -> Option<String> {
+        match self.command().args(&["--version", "--verbose"]).output() {
+            Ok(ref output) if output.status.success() => {
+                Some(String::from_utf8_lossy(&output.stdout).into_owned())
+            },
+            _ => None,
+        }
+    }
+
+    /// Returns the release version of the compiler, if the compiler could be run
+    pub fn release(&self) -> Option<Version> {
+        let version = try_opt!(self.version());
+
+        // `release: <version>`, or `rustc <version> (<commit-hash> <commit-date>)` if the compiler
+        // doesn't support `--verbose`
+        match version.lines().filter(|line| line.starts_with("release: ")).next() {
+            Some(line) => Version::parse(&line["release: ".len()..]),
+            None => version.split_whitespace().nth(1).and_then(Version::parse),
+        }
+    }
+
+    /// Returns the sysroot of the compiler, if the compiler can tell
+    ///
+    /// Unless the sysroot was given, this runs the compiler.
+    pub fn sysroot(&self) -> Option<PathBuf> {
+        if let Some(ref sysroot) = self.sysroot {
+            return Some(sysroot.clone())
+        }
+
+        match self.command().args(&["--print", "sysroot"]).output() {
+            Ok(ref output) if output.status.success() => {
+                let sysroot = String::from_utf8_lossy(&output.stdout);
+                let sysroot = sysroot.trim();
+
+                if sysroot.is_empty() {
+                    None
+                } else {
+                    Some(PathBuf::from(sysroot))
+                }
+            },
+            _ => None,
+        }
+    }
+
+    /// Returns a command that invokes the compiler with the sysroot
+    fn command(&self) -> Command {
+        let mut cmd = Command::new(&self.rustc);
+
+        if let Some(ref sysroot) = self.sysroot {
+            cmd.arg("--sysroot").arg(sysroot);
+        }
+
+        cmd
+    }
+}
+
+/// Resolves a relative `path` against the current directory, if the current directory is known
+fn absolute(path: PathBuf) -> PathBuf {
+    if path.is_absolute() {
+        return path
+    }
+
+    match env::current_dir() {
+        Ok(current_dir) => current_dir.join(path),
+        Err(_) => path,
+    }
+}
+
+/// Compiler release version, e.g. `1.20.0`
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct Version {
+    /// Major version
+    pub major: u32,
+    /// Minor version
+    pub minor: u32,
+    /// Patch version, `None` if it was omitted, as in `1.20`
+    pub patch: Option<u32>,
+}
+
+impl Version {
+    /// Parses a `<major>.<minor>[.<patch>]` version, pre-release suffixes like `-nightly` are
+    /// ignored
+    pub fn parse(version: &str) -> Option<Version> {
+        let version = version.trim().split('-').next().unwrap_or("");
+        let mut parts = version.split('.').map(|part| part.parse().ok());
+
+        let major = try_opt!(try_opt!(parts.next()));
+        let minor = try_opt!(try_opt!(parts.next()));
+        let patch = match parts.next() {
+            None => None,
+            Some(patch) => Some(try_opt!(patch)),
+        };
+
+        if parts.next().is_some() {
+            return None
+        }
+
+        Some(Version {
+            major: major,
+            minor: minor,
+            patch: patch,
+        })
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.patch {
+            None => write!(f, "{}.{}", self.major, self.minor),
+            Some(patch) => write!(f, "{}.{}.{}", self.major, self.minor, patch),
+        }
+    }
+}
+
 /// Compiler stderr
 pub struct Stderr {
+    /// Binary produced by a `RunFail`/`RunPass` compilation
+    executable: PathBuf,
+    /// Messages extracted from the JSON diagnostics, `None` if the compiler doesn't support the
+    /// JSON output
+    messages: Option<Vec<json::Message>>,
     source: String,
     stderr: String,
+    /// Did the compilation succeed?
+    success: bool,
+    /// Directory where the source file was compiled, and where the produced binary lives
+    temp_dir: TempDir,
 }
 
 /// Compiles a source file, and returns the compiler stderr
-pub fn compile<P: ?Sized>(source: &P, library_path: &str) -> Result<Stderr, Error> where
+///
+/// The `aux_builds` crates are compiled first, as rlibs, and made available to the source file via
+/// `--extern`. The extra `flags` are passed to the compiler when compiling the source file. In
+/// `CheckPass` mode the source file is only type checked (`--emit=metadata`).
+pub fn compile<P: ?Sized>(
+    compiler: &Compiler,
+    source: &P,
+    library_paths: &[PathBuf],
+    aux_builds: &[PathBuf],
+    flags: &[String],
+    mode: Mode,
+) -> Result<Stderr, Error> where
     P: AsRef<Path>,
 {
-    Stderr::new(source.as_ref(), library_path)
+    Stderr::new(compiler, source.as_ref(), library_paths, aux_builds, flags, mode)
+}
+
+/// Returns a `compiler` command that runs in `temp_dir` and searches the `library_paths`
+fn rustc(
+    compiler: &Compiler,
+    current_dir: &Path,
+    temp_dir: &Path,
+    library_paths: &[PathBuf],
+) -> Command {
+    let mut cmd = compiler.command();
+    cmd.current_dir(temp_dir);
+
+    for path in library_paths {
+        cmd.arg("-L").arg(&current_dir.join(path));
+    }
+
+    cmd
 }
 
 impl Stderr {
-    fn new(path: &Path, library_path: &str) -> Result<Stderr, Error> {
+    fn new(
+        compiler: &Compiler,
+        path: &Path,
+        library_paths: &[PathBuf],
+        aux_builds: &[PathBuf],
+        flags: &[String],
+        mode: Mode,
+    ) -> Result<Stderr, Error> {
         let current_dir = try!(env::current_dir());
         let temp_dir = try!(TempDir::new_in(&current_dir, "cfail"));
         let source = current_dir.join(path);
 
-        let mut cmd = Command::new("rustc");
-        cmd.current_dir(temp_dir.path());
+        let mut externs = vec![];
+        for aux in aux_builds {
+            let aux = current_dir.join(aux);
+            let name = match aux.file_stem() {
+                Some(stem) => stem.to_string_lossy().replace("-", "_"),
+                None => return Err(Error::AuxBuild(aux.to_string_lossy().into_owned(), None)),
+            };
 
-        for path in library_path.split(':') {
-            cmd.arg("-L").arg(&current_dir.join(path));
+            let mut cmd = rustc(compiler, &current_dir, temp_dir.path(), library_paths);
+            cmd.args(&["--crate-type", "rlib", "--crate-name", &name, "--out-dir"]);
+            cmd.arg(temp_dir.path());
+            cmd.arg(&aux);
+
+            let output = try!(cmd.output());
+
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+
+                return Err(Error::AuxBuild(aux.to_string_lossy().into_owned(), Some(stderr)))
+            }
+
+            let rlib = temp_dir.path().join(format!("lib{}.rlib", name));
+            externs.push(format!("{}={}", name, rlib.to_string_lossy()));
+        }
+
+        let stem = source.file_stem().map(|stem| stem.to_string_lossy().into_owned());
+        let executable = temp_dir.path().join(&format!("{}{}",
+                                                       stem.unwrap_or("main".to_string()),
+                                                       env::consts::EXE_SUFFIX));
+
+        let run = |json: bool| -> io::Result<Output> {
+            let mut cmd = rustc(compiler, &current_dir, temp_dir.path(), library_paths);
+            cmd.arg("-L").arg(temp_dir.path());
+
+            for extern_ in &externs {
+                cmd.arg("--extern").arg(extern_);
+            }
+
+            if json {
+                cmd.arg("--error-format=json");
+            }
+
+            match mode {
+                Mode::CheckPass => {
+                    cmd.arg("--emit=metadata");
+                },
+                Mode::RunFail | Mode::RunPass => {
+                    cmd.arg("-o").arg(&executable);
+                },
+                Mode::BuildPass | Mode::CompileFail => {},
+            }
+
+            cmd.args(flags);
+            cmd.arg(&source);
+
+            cmd.output()
+        };
+
+        let mut output = try!(run(true));
+        let mut json = true;
+
+        if !output.status.success() && !is_json(&output.stderr) {
+            // older compilers don't understand `--error-format`, fall back to the human readable
+            // format
+            output = try!(run(false));
+            json = false;
         }
 
-        cmd.arg(&source);
+        let source = source.to_string_lossy().into_owned();
+        let mut stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        let messages = if json {
+            let diagnostics = try!(json::parse(&stderr));
 
-        let output = try!(cmd.output());
+            // keep the human readable version of the output around
+            stderr = json::render(&stderr);
 
-        if output.status.success() {
-            Err(Error::SuccessfulCompilation)
+            Some(json::messages(&diagnostics, &source))
         } else {
-            Ok(Stderr {
-                source: source.to_string_lossy().into_owned(),
-                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
-            })
+            None
+        };
+
+        Ok(Stderr {
+            executable: executable,
+            messages: messages,
+            source: source,
+            stderr: stderr,
+            success: output.status.success(),
+            temp_dir: temp_dir,
+        })
+    }
+
+    /// Did the compilation succeed?
+    pub fn success(&self) -> bool {
+        self.success
+    }
+
+    /// Runs the binary produced by a `RunFail`/`RunPass` compilation, in the directory where it
+    /// was compiled
+    pub fn run(&self) -> io::Result<Output> {
+        Command::new(&self.executable).current_dir(self.temp_dir.path()).output()
+    }
+
+    /// Returns the built-in normalization rules for this compilation by a compiler with this
+    /// `sysroot`, followed by the user-defined `rules`, see the `normalize` module
+    pub fn normalizer<'a>(
+        &self,
+        sysroot: Option<&Path>,
+        rules: &'a [(Regex, String)],
+    ) -> Normalizer<'a> {
+        let source = PathBuf::from(&self.source);
+        let dir = source.parent().unwrap_or(Path::new(""));
+
+        Normalizer::new(dir, self.temp_dir.path(), sysroot, rules)
+    }
+
+    /// Normalizes the compiler output
+    ///
+    /// The stderr and the text of the compiler messages are normalized, and the path to the source
+    /// file is normalized as well so the messages can still be traced back to the source file.
+    pub fn normalize(&mut self, normalizer: &Normalizer) {
+        self.stderr = normalizer.apply(&self.stderr);
+
+        if let Some(ref mut messages) = self.messages {
+            for message in messages {
+                message.text = normalizer.apply(&message.text);
+            }
         }
+
+        self.source = normalizer.apply(&self.source);
+    }
+
+    /// Returns the compiler stderr in the human readable format
+    pub fn stderr(&self) -> &str {
+        &self.stderr
     }
 
     /// Parses the compiler stderr and returns a list of compiler messages
@@ -58,25 +372,85 @@ impl Stderr {
         use std::collections::btree_map::Entry::{Occupied, Vacant};
 
         let mut map: LineMap<Messages> = BTreeMap::new();
-        let stderr: &str = &self.stderr;
 
-        let parser = Parser::new(stderr, &self.source);
+        {
+            let mut insert = |ln, kind, message| {
+                match map.entry(ln) {
+                    Occupied(mut entry) => {
+                        entry.get_mut().insert(kind, message)
+                    },
+                    Vacant(entry) => {
+                        let mut messages = Messages::new();
+                        messages.insert(kind, message);
+                        entry.insert(messages);
+                    },
+                }
+            };
 
-        for lkm in parser {
-            let (ln, kind, message) = try!(lkm);
+            if let Some(ref messages) = self.messages {
+                for message in messages {
+                    insert(message.line, message.kind, Message {
+                        code: message.code.as_ref().map(|code| &code[..]),
+                        column: Some(message.column),
+                        last_column: message.last_column,
+                        text: &message.text,
+                    });
+                }
+            } else {
+                let stderr: &str = &self.stderr;
 
-            match map.entry(ln) {
-                Occupied(mut entry) => {
-                    entry.get_mut().insert(kind, message)
-                },
-                Vacant(entry) => {
-                    let mut messages = Messages::new();
-                    messages.insert(kind, message);
-                    entry.insert(messages);
-                },
+                let parser = Parser::new(stderr, &self.source);
+
+                for lkm in parser {
+                    let (ln, kind, message) = try!(lkm);
+
+                    insert(ln, kind, message);
+                }
             }
         }
 
         Ok(map)
     }
 }
+
+/// Does the stderr contain JSON diagnostics?
+fn is_json(stderr: &[u8]) -> bool {
+    String::from_utf8_lossy(stderr).lines().any(|line| line.starts_with("{"))
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::path::{Path, PathBuf};
+
+    use super::{Compiler, Version};
+
+    #[test]
+    fn new() {
+        let current_dir = env::current_dir().unwrap();
+
+        let compiler = Compiler::new(PathBuf::from("bin/rustc"), Some(PathBuf::from("sysroot")));
+        assert_eq!(compiler.rustc, current_dir.join("bin/rustc"));
+        assert_eq!(compiler.sysroot, Some(current_dir.join("sysroot")));
+
+        let compiler = Compiler::new(PathBuf::from("./rustc"), None);
+        assert_eq!(compiler.rustc, current_dir.join("./rustc"));
+
+        // looked up in the `PATH`
+        assert_eq!(Compiler::new(PathBuf::from("rustc"), None).rustc, Path::new("rustc"));
+
+        let compiler = Compiler::new(PathBuf::from("/usr/bin/rustc"), Some(PathBuf::from("/usr")));
+        assert_eq!(compiler.rustc, Path::new("/usr/bin/rustc"));
+        assert_eq!(compiler.sysroot, Some(PathBuf::from("/usr")));
+    }
+
+    #[test]
+    fn version() {
+        assert_eq!(Version::parse("1.20"), Some(Version { major: 1, minor: 20, patch: None }));
+        assert_eq!(Version::parse("1.9.0-nightly"),
+                   Some(Version { major: 1, minor: 9, patch: Some(0) }));
+        assert_eq!(Version::parse("1"), None);
+        assert_eq!(Version::parse("1.20.0.0"), None);
+        assert!(Version::parse("1.9.0").unwrap() < Version::parse("1.20.0").unwrap());
+    }
+}
@@ -8,12 +8,20 @@ use {BytePos, Kind};
 /// Tokens found in `rustc` stderr
 #[derive(Debug, PartialEq)]
 pub enum Token {
+    /// `-->`
+    Arrow,
+    /// `[E0308]`
+    Code(u32),
     /// `:`
     Colon,
+    /// `=`
+    Equals,
     /// `error`
     Kind(Kind),
     /// `123`
     Number(u32),
+    /// `|`
+    Pipe,
     /// ` `
     Whitespace,
 }
@@ -21,11 +29,15 @@ pub enum Token {
 /// EBNF:
 ///
 /// ``` text
+/// arrow                = "-->" ;
+/// code                 = "[E" , digit , { digit } , "]" ;
 /// colon                = ":" ;
 /// digit                = "0" | digit excluding zero ;
 /// digit excluding zero = "1" | "2" | "3" | "4" | "5" | "6" | "7" | "8" | "9" ;
+/// equals               = "=" ;
 /// kind                 = "error" | "help" | "note" | "warning" ;
 /// number               = digit excluding zero , { digit } ;
+/// pipe                 = "|" ;
 /// whitespace           = " " ;
 /// ```
 pub struct Lexer<'a> {
@@ -49,9 +61,13 @@ impl<'a> Lexer<'a> {
         match self.next() {
             None => Err(()),
             Some(result) => match (try!(result), tok) {
+                (Token::Arrow, Token::Arrow) => Ok(()),
+                (Token::Code(_), Token::Code(_)) => Ok(()),
                 (Token::Colon, Token::Colon) => Ok(()),
+                (Token::Equals, Token::Equals) => Ok(()),
                 (Token::Kind(_), Token::Kind(_)) => Ok(()),
                 (Token::Number(_), Token::Number(_)) => Ok(()),
+                (Token::Pipe, Token::Pipe) => Ok(()),
                 (Token::Whitespace, Token::Whitespace) => Ok(()),
                 _ => Err(())
             },
@@ -82,6 +98,41 @@ impl<'a> Iterator for Lexer<'a> {
                 let kind = match c {
                     ' ' => return Ok(Token::Whitespace),
                     ':' => return Ok(Token::Colon),
+                    '=' => return Ok(Token::Equals),
+                    '|' => return Ok(Token::Pipe),
+                    '-' => {
+                        if self.input[i..].starts_with("-->") {
+                            self.iter.next();
+                            self.iter.next();
+
+                            return Ok(Token::Arrow)
+                        } else {
+                            return self.error()
+                        }
+                    },
+                    '[' => {
+                        let rest = &self.input[i+1..];
+                        let end = match rest.find(']') {
+                            Some(end) if rest.starts_with("E") && end > 1 => end,
+                            _ => return self.error(),
+                        };
+
+                        let digits = &rest[1..end];
+                        if !digits.chars().all(|c| c.is_digit(10)) {
+                            return self.error()
+                        }
+
+                        // `E` + digits + `]`
+                        for _ in 0..end+1 {
+                            self.iter.next();
+                        }
+
+                        return match digits.parse() {
+                            Ok(code) => Ok(Token::Code(code)),
+                            // too many digits
+                            Err(_) => self.error(),
+                        }
+                    },
                     'e' => Kind::Error,
                     'h' => Kind::Help,
                     'n' => Kind::Note,
@@ -99,7 +150,11 @@ impl<'a> Iterator for Lexer<'a> {
                             }
                         }
 
-                        return Ok(Token::Number(self.input[start..end].parse().unwrap()))
+                        return match self.input[start..end].parse() {
+                            Ok(number) => Ok(Token::Number(number)),
+                            // too many digits
+                            Err(_) => self.error(),
+                        }
                     },
                     _ => {
                         return self.error()
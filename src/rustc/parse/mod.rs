@@ -3,7 +3,7 @@
 use std::iter::Peekable;
 use std::str::Lines;
 
-use {BytePos, Error, Kind, Line};
+use {BytePos, Error, Kind, Line, Message};
 
 use self::lexer::{Lexer, Token};
 
@@ -27,10 +27,34 @@ pub mod lexer;
 /// ```
 ///
 /// These compiler spans will be ignored by the parser.
+///
+/// Newer compilers use a multi-line layout instead, where the header is followed by an arrow that
+/// points to the source location:
+///
+/// ``` text
+/// <kind>[<code>]: <message>
+///  --> <path>:<line>:<column>
+///   |
+/// 2 |     let _: i8 = 0u8;
+///   |                 ^^^ expected `i8`, found `u8`
+///   |
+///   = note: <message>
+/// help: <message>
+/// ```
+///
+/// where `[<code>]` is optional. The `|` gutter lines are ignored, `= <kind>: <message>` trailers
+/// and headers that are not followed by an arrow (sub-diagnostics) point to the same line as the
+/// diagnostic they belong to, and so do the sub-diagnostics whose arrow points to another file,
+/// like in the JSON diagnostics. A blank line ends the diagnostic. Headers that don't belong to
+/// any diagnostic and don't point to the source file (e.g. "aborting due to ...") are ignored.
 pub struct Parser<'a> {
+    /// Is the parser inside a multi-line diagnostic?
+    in_diagnostic: bool,
     input: &'a str,
     last_line: Option<usize>,
     lines: Peekable<Lines<'a>>,
+    /// Line and column the current multi-line diagnostic points to
+    location: Option<(Line, u32)>,
     path: &'a str,
     start_of_line: BytePos,
 }
@@ -39,14 +63,82 @@ impl<'a> Parser<'a> {
     /// Creates a new parser for the compiler stderr
     pub fn new(stderr: &'a str, path: &'a str) -> Parser<'a> {
         Parser {
+            in_diagnostic: false,
             input: stderr,
             last_line: None,
             lines: stderr.lines().peekable(),
+            location: None,
             path: path,
             start_of_line: 0,
         }
     }
 
+    /// Parses the ` --> <path>:<line>:<column>` line, returns `None` if the arrow doesn't point to
+    /// the source file
+    fn arrow(&self, line: &str) -> Option<(Line, u32)> {
+        let mut lexer = Lexer::new(line);
+
+        let offset = match (|| {
+            loop {
+                match lexer.next() {
+                    Some(Ok(Token::Whitespace)) => {},
+                    Some(Ok(Token::Arrow)) => break,
+                    _ => return Err(()),
+                }
+            }
+
+            try!(lexer.eat(Token::Whitespace));
+
+            Ok(lexer.next_byte_pos())
+        })() {
+            Err(_) => return None,
+            Ok(offset) => offset,
+        };
+
+        let location = &line[offset..];
+
+        if !location.starts_with(self.path) {
+            return None
+        }
+
+        let mut lexer = Lexer::new(&location[self.path.len()..]);
+
+        // <path>:<line>:<column>
+        match (lexer.next(), lexer.next(), lexer.next(), lexer.next()) {
+            (
+                Some(Ok(Token::Colon)),
+                Some(Ok(Token::Number(line))),
+                Some(Ok(Token::Colon)),
+                Some(Ok(Token::Number(column))),
+            ) => Some((Line(line), column)),
+            _ => None,
+        }
+    }
+
+    /// Consumes the lines that continue the message that starts in the current line, and returns
+    /// the absolute byte position where the message ends
+    fn continuation(&mut self, line: &'a str, indented: bool) -> BytePos {
+        let mut end = self.start_of_line + line.len();
+
+        while let Some(next_line) = self.peek_line() {
+            if next_line.trim().is_empty() ||
+                next_line.starts_with(self.path) ||
+                (indented && !next_line.starts_with(" ")) ||
+                is_arrow(next_line) ||
+                is_gutter(next_line) ||
+                header(next_line).is_some() ||
+                trailer(next_line).is_some()
+            {
+                break
+            }
+
+            self.next_line();
+            end = self.start_of_line + next_line.len();
+        }
+
+        end
+    }
+
     fn peek_line(&mut self) -> Option<&'a str> {
         self.lines.peek().map(|&line| line)
     }
@@ -64,18 +156,15 @@ impl<'a> Parser<'a> {
 }
 
 impl<'a> Iterator for Parser<'a> {
-    type Item = Result<(Line, Kind, &'a str), Error>;
+    type Item = Result<(Line, Kind, Message<'a>), Error>;
 
-    fn next(&mut self) -> Option<Result<(Line, Kind, &'a str), Error>> {
+    fn next(&mut self) -> Option<Result<(Line, Kind, Message<'a>), Error>> {
         while let Some(line) = self.next_line() {
             // <path>
-            let (ln, kind, offset) = if line.starts_with(self.path) {
+            let (ln, columns, kind, offset) = if line.starts_with(self.path) {
                 let mut lexer = Lexer::new(&line[self.path.len()..]);
 
                 match (|| {
-                    // Any number
-                    const ANY: u32 = 0;
-
                     // <path>:
                     try!(lexer.eat(Token::Colon));
 
@@ -98,16 +187,37 @@ impl<'a> Iterator for Parser<'a> {
                     }
 
                     // <path>:<line>:<bytepos_start>
-                    try!(lexer.eat(Token::Number(ANY)));
+                    let column = if let Some(Ok(Token::Number(column))) = lexer.next() {
+                        column
+                    } else {
+                        return Err(())
+                    };
 
                     // <path>:<line>:<bytepos_start>: <line>
                     try!(lexer.eat(Token::Colon));
                     try!(lexer.eat(Token::Whitespace));
-                    try!(lexer.eat(Token::Number(ANY)));
+                    let line_end = if let Some(Ok(Token::Number(line))) = lexer.next() {
+                        Line(line)
+                    } else {
+                        return Err(())
+                    };
 
                     // <path>:<line>:<bytepos_start>: <line>:<bytepos_end>
                     try!(lexer.eat(Token::Colon));
-                    try!(lexer.eat(Token::Number(ANY)));
+                    let column_end = if let Some(Ok(Token::Number(column))) = lexer.next() {
+                        column
+                    } else {
+                        return Err(())
+                    };
+
+                    // NB `<bytepos_end>` is exclusive
+                    let last_column = if line_end != line {
+                        None
+                    } else if column_end > column {
+                        Some(column_end - 1)
+                    } else {
+                        Some(column)
+                    };
 
                     // <path>:<line>:<bytepos_start>: <line>:<bytepos_end> <kind>
                     try!(lexer.eat(Token::Whitespace));
@@ -122,13 +232,70 @@ impl<'a> Iterator for Parser<'a> {
                     try!(lexer.eat(Token::Whitespace));
                     let offset = lexer.next_byte_pos();
 
-                    Ok(Some((line, kind, self.path.len() + offset)))
+                    Ok(Some((line, (column, last_column), kind, self.path.len() + offset)))
                 })() {
                     Err(_) => return Some(Err(Error::ParseStderr(line.to_string()))),
                     Ok(None) => continue,
                     Ok(Some(payload)) => payload,
                 }
+            } else if line.trim().is_empty() {
+                self.in_diagnostic = false;
+                self.location = None;
+                continue
+            } else if let Some((kind, code, offset)) = header(line) {
+                let start = self.start_of_line + offset;
+                let end = self.continuation(line, false);
+
+                let is_child = self.in_diagnostic;
+                self.in_diagnostic = true;
+
+                let arrow = match self.peek_line() {
+                    Some(next_line) if is_arrow(next_line) => {
+                        self.next_line();
+                        self.arrow(next_line)
+                    },
+                    _ => None,
+                };
+
+                let location = if is_child {
+                    arrow.or(self.location)
+                } else {
+                    self.location = arrow;
+                    arrow
+                };
+
+                match location {
+                    Some((ln, column)) => {
+                        let message = Message {
+                            code: code,
+                            column: Some(column),
+                            last_column: None,
+                            text: &self.input[start..end],
+                        };
+
+                        return Some(Ok((ln, kind, message)))
+                    },
+                    None => continue,
+                }
+            } else if let Some((kind, offset)) = trailer(line) {
+                let start = self.start_of_line + offset;
+                let end = self.continuation(line, true);
+
+                match self.location {
+                    Some((ln, column)) => {
+                        let message = Message {
+                            code: None,
+                            column: Some(column),
+                            last_column: None,
+                            text: &self.input[start..end],
+                        };
+
+                        return Some(Ok((ln, kind, message)))
+                    },
+                    None => continue,
+                }
             } else {
+                // gutter lines, source snippets, "For more information ...", etc
                 continue
             };
 
@@ -151,7 +318,7 @@ impl<'a> Iterator for Parser<'a> {
                     next_line.starts_with("error: aborting due to ")
                 {
                     let end = self.start_of_line+curr_line.len();
-                    return Some(Ok((ln, kind, &self.input[start..end])))
+                    return Some(Ok((ln, kind, old_message(columns, &self.input[start..end]))))
                 } else {
                     curr_line = next_line;
                     self.next_line();
@@ -159,9 +326,234 @@ impl<'a> Iterator for Parser<'a> {
             }
 
             let end = self.start_of_line+curr_line.len();
-            return Some(Ok((ln, kind, &self.input[start..end])))
+            return Some(Ok((ln, kind, old_message(columns, &self.input[start..end]))))
         }
 
         None
     }
 }
+
+/// Parses the `<kind>[<code>]: <message>` header, returns the error code and the byte position
+/// where the message starts
+fn header(line: &str) -> Option<(Kind, Option<&str>, BytePos)> {
+    let mut lexer = Lexer::new(line);
+
+    (|| {
+        let kind = if let Some(Ok(Token::Kind(kind))) = lexer.next() {
+            kind
+        } else {
+            return Err(())
+        };
+
+        let start = lexer.next_byte_pos();
+        let code = match lexer.next() {
+            Some(Ok(Token::Code(_))) => {
+                // strip the brackets
+                let code = &line[start+1..lexer.next_byte_pos()-1];
+
+                try!(lexer.eat(Token::Colon));
+
+                Some(code)
+            },
+            Some(Ok(Token::Colon)) => None,
+            _ => return Err(()),
+        };
+
+        try!(lexer.eat(Token::Whitespace));
+
+        Ok((kind, code, lexer.next_byte_pos()))
+    })().ok()
+}
+
+/// Builds a message out of the old single-line header format, which reports the start and last
+/// `columns` of the span
+///
+/// The trailing ` [<code>]` is removed from the text, so the text is the same as in the other
+/// formats.
+fn old_message((column, last_column): (u32, Option<u32>), text: &str) -> Message {
+    let code = trailing_code(text);
+    let text = match code {
+        Some(code) => &text[..text.len() - " [".len() - code.len() - "]".len()],
+        None => text,
+    };
+
+    Message {
+        code: code,
+        column: Some(column),
+        last_column: last_column,
+        text: text,
+    }
+}
+
+/// Extracts the ` [<code>]` that old compilers appended to the messages
+fn trailing_code(message: &str) -> Option<&str> {
+    if !message.ends_with("]") {
+        return None
+    }
+
+    message.rfind(" [").and_then(|start| {
+        let code = &message[start+" [".len()..message.len()-"]".len()];
+
+        let mut lexer = Lexer::new(&message[start+" ".len()..]);
+        match (lexer.next(), lexer.next()) {
+            (Some(Ok(Token::Code(_))), None) => Some(code),
+            _ => None,
+        }
+    })
+}
+
+/// Parses the `  = <kind>: <message>` trailer, returns the byte position where the message starts
+fn trailer(line: &str) -> Option<(Kind, BytePos)> {
+    let mut lexer = Lexer::new(line);
+
+    (|| {
+        loop {
+            match lexer.next() {
+                Some(Ok(Token::Whitespace)) => {},
+                Some(Ok(Token::Equals)) => break,
+                _ => return Err(()),
+            }
+        }
+
+        try!(lexer.eat(Token::Whitespace));
+
+        let kind = if let Some(Ok(Token::Kind(kind))) = lexer.next() {
+            kind
+        } else {
+            return Err(())
+        };
+
+        try!(lexer.eat(Token::Colon));
+        try!(lexer.eat(Token::Whitespace));
+
+        Ok((kind, lexer.next_byte_pos()))
+    })().ok()
+}
+
+/// Is this a ` --> <path>:<line>:<column>` line?
+fn is_arrow(line: &str) -> bool {
+    line.trim_left().starts_with("-->")
+}
+
+/// Is this a `<line> | <source>`, `  |` or `...` line? Also matches the ` ::: <path>` lines that
+/// point to other files.
+fn is_gutter(line: &str) -> bool {
+    let trimmed = line.trim();
+
+    if trimmed == "..." || trimmed.starts_with(":::") {
+        return true
+    }
+
+    let mut lexer = Lexer::new(line).skip_while(|tok| *tok == Ok(Token::Whitespace));
+
+    match lexer.next() {
+        Some(Ok(Token::Pipe)) => true,
+        Some(Ok(Token::Number(_))) => match lexer.find(|tok| *tok != Ok(Token::Whitespace)) {
+            Some(Ok(Token::Pipe)) => true,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {Kind, Line};
+
+    use super::Parser;
+
+    #[test]
+    fn trailing_code() {
+        let message = "mismatched types:\n expected `i8`,\n    found `u8` [E0308]";
+
+        assert_eq!(super::trailing_code(message), Some("E0308"));
+        assert_eq!(super::trailing_code("unresolved name `foo`"), None);
+        assert_eq!(super::trailing_code("expected one of `[a]`"), None);
+    }
+
+    #[test]
+    fn multiline_header() {
+        let stderr = "\
+error[E0308]: mismatched types
+ --> /src/a.rs:5:44
+  |
+5 |     let _: () = v;
+  |            --   ^ expected `()`, found `Vec<i32>`
+  |            |
+  |            expected due to this
+  |
+  = note: expected unit type `()`
+                found struct `Vec<i32>`
+note: function defined here
+ --> /src/a.rs:1:4
+  |
+1 | fn f(x: i32) {}
+  |    ^ ------
+note: required by a bound in `g`
+ --> /src/c.rs:3:9
+  |
+3 | fn g<T: Copy>() {}
+  |         ^^^^ required by this bound in `g`
+help: consider removing the binding
+  |
+5 -     let _: () = v;
+  |
+
+warning: unused variable: `x`
+ --> /src/b.rs:1:6
+  |
+  = note: `#[warn(unused_variables)]` on by default
+
+error: aborting due to 1 previous error
+
+For more information about this error, try `rustc --explain E0308`.
+";
+
+        let messages = Parser::new(stderr, "/src/a.rs").collect::<Result<Vec<_>, _>>().unwrap();
+
+        assert_eq!(messages[0].2.code, Some("E0308"));
+        assert_eq!(messages[0].2.column, Some(44));
+
+        let messages: Vec<_> = messages.into_iter().map(|(ln, kind, message)| {
+            (ln, kind, message.text)
+        }).collect();
+
+        let note = "expected unit type `()`\n                found struct `Vec<i32>`";
+        assert_eq!(messages, vec![
+            (Line(5), Kind::Error, "mismatched types"),
+            (Line(5), Kind::Note, note),
+            (Line(1), Kind::Note, "function defined here"),
+            (Line(5), Kind::Note, "required by a bound in `g`"),
+            (Line(5), Kind::Help, "consider removing the binding"),
+        ]);
+    }
+
+    #[test]
+    fn old_header() {
+        let stderr = "\
+/src/a.rs:2:17: 2:20 error: mismatched types:
+ expected `i8`,
+    found `u8` [E0308]
+/src/a.rs:2     let _: i8 = 0u8;
+                            ^~~
+/src/a.rs:3:5: 4:6 error: unresolved name `foo`
+error: aborting due to 2 previous errors
+";
+
+        let messages = Parser::new(stderr, "/src/a.rs").collect::<Result<Vec<_>, _>>().unwrap();
+
+        assert_eq!(messages[0].2.code, Some("E0308"));
+        assert_eq!(messages[0].2.text, "mismatched types:\n expected `i8`,\n    found `u8`");
+        assert_eq!((messages[0].2.column, messages[0].2.last_column), (Some(17), Some(19)));
+        assert_eq!(messages[1].0, Line(3));
+        assert_eq!((messages[1].2.column, messages[1].2.last_column), (Some(5), None));
+    }
+
+    #[test]
+    fn too_many_digits() {
+        assert_eq!(super::header("error[E99999999999]: mismatched types"), None);
+
+        let stderr = "/src/a.rs:99999999999:1: 1:2 error: mismatched types\n";
+        assert!(Parser::new(stderr, "/src/a.rs").next().unwrap().is_err());
+    }
+}
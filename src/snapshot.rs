@@ -0,0 +1,186 @@
+//! Expected output files
+//!
+//! A source file `foo.rs` can be accompanied by these files, the snapshots:
+//!
+//! - `foo.stderr`, the expected compiler stderr
+//! - `foo.run.stdout` and `foo.run.stderr`, the expected output of the test binary, only for
+//!   `RunFail` and `RunPass` tests
+//!
+//! Source files with revisions have one snapshot per revision instead, e.g.
+//! `foo.<revision>.stderr`, which is why revisions can't be named `run` or contain a `.`.
+//!
+//! A snapshot is only checked if its file exists, bless mode overwrites the existing snapshots but
+//! never creates new ones.
+
+use std::cmp;
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write, self};
+use std::path::{Path, PathBuf};
+
+/// Lines of context around the changes of a diff
+const CONTEXT: usize = 3;
+
+/// Path to the snapshot with this `extension`, e.g. `run.stdout`, of the `source` file compiled
+/// under `revision`
+pub fn path(source: &Path, revision: Option<&str>, extension: &str) -> PathBuf {
+    match revision {
+        None => source.with_extension(extension),
+        Some(revision) => source.with_extension(&format!("{}.{}", revision, extension)),
+    }
+}
+
+/// Reads a snapshot, returns `None` if the file doesn't exist
+pub fn read(path: &Path) -> io::Result<Option<String>> {
+    let mut file = match File::open(path) {
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+        Ok(file) => file,
+    };
+
+    let mut contents = String::new();
+    try!(file.read_to_string(&mut contents));
+
+    Ok(Some(contents))
+}
+
+/// Overwrites a snapshot with the `actual` output
+pub fn write(path: &Path, actual: &str) -> io::Result<()> {
+    try!(File::create(path)).write_all(actual.as_bytes())
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Op {
+    Delete,
+    Equal,
+    Insert,
+}
+
+/// Line by line unified diff between the `expected` and the `actual` output, returns `None` if
+/// there are no differences
+pub fn diff(expected: &str, actual: &str) -> Option<String> {
+    let old: Vec<_> = expected.lines().collect();
+    let new: Vec<_> = actual.lines().collect();
+
+    // lcs[i][j]: length of the longest common subsequence of `old[i..]` and `new[j..]`
+    let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                cmp::max(lcs[i + 1][j], lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut ops = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            ops.push((Op::Equal, old[i]));
+            i += 1;
+            j += 1;
+        } else if j == new.len() || (i < old.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push((Op::Delete, old[i]));
+            i += 1;
+        } else {
+            ops.push((Op::Insert, new[j]));
+            j += 1;
+        }
+    }
+
+    let changes: Vec<_> = (0..ops.len()).filter(|&k| ops[k].0 != Op::Equal).collect();
+    if changes.is_empty() {
+        return None
+    }
+
+    // number of `old`/`new` lines before each operation
+    let mut lines = vec![(0, 0)];
+    for &(op, _) in &ops {
+        let (old, new) = lines[lines.len() - 1];
+
+        lines.push(match op {
+            Op::Delete => (old + 1, new),
+            Op::Equal => (old + 1, new + 1),
+            Op::Insert => (old, new + 1),
+        });
+    }
+
+    let mut buffer = String::from("--- expected\n+++ actual\n");
+    let mut changes = changes.into_iter().peekable();
+    while let Some(first) = changes.next() {
+        let mut last = first;
+
+        // changes that are close together go in the same hunk
+        while let Some(&next) = changes.peek() {
+            if next - last > 2 * CONTEXT + 1 {
+                break
+            }
+
+            last = next;
+            changes.next();
+        }
+
+        let start = if first > CONTEXT { first - CONTEXT } else { 0 };
+        let end = cmp::min(ops.len(), last + 1 + CONTEXT);
+        let (old_start, new_start) = lines[start];
+        let (old_end, new_end) = lines[end];
+
+        buffer.push_str(&format!("@@ -{} +{} @@\n",
+                                 range(old_start, old_end - old_start),
+                                 range(new_start, new_end - new_start)));
+
+        for &(op, line) in &ops[start..end] {
+            let prefix = match op {
+                Op::Delete => '-',
+                Op::Equal => ' ',
+                Op::Insert => '+',
+            };
+
+            buffer.push(prefix);
+            buffer.push_str(line);
+            buffer.push('\n');
+        }
+    }
+
+    Some(buffer)
+}
+
+/// Formats the range of a hunk, `start` is the number of lines that precede the hunk
+fn range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        len => format!("{},{}", start + 1, len),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn diff() {
+        assert_eq!(super::diff("a\nb\n", "a\nb"), None);
+
+        let expected = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n";
+        let actual = "1\n2\n3\n4\nfive\n6\n7\n8\n9\n10\n11\n12\n13\n";
+
+        assert_eq!(super::diff(expected, actual).unwrap(), "\
+--- expected
++++ actual
+@@ -2,7 +2,7 @@
+ 2
+ 3
+ 4
+-5
++five
+ 6
+ 7
+ 8
+@@ -10,3 +10,4 @@
+ 10
+ 11
+ 12
++13
+");
+    }
+}
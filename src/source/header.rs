@@ -0,0 +1,370 @@
+//! Header directives
+//!
+//! Directives are line comments of the form `// <name>` or `// <name>: <value>`, and can appear
+//! anywhere in the source file. Comments that don't name a known directive are ignored.
+
+use std::iter::Peekable;
+use std::mem;
+use std::str::CharIndices;
+
+use regex::Regex;
+
+use {BytePos, Mode, Span};
+
+use rustc::Version;
+use source;
+use source::parse::Error;
+use source::parse::lexer::Token;
+
+/// Directives found in a source file
+#[derive(Debug)]
+pub struct Header<'a> {
+    /// `// aux-build:<file>`
+    pub aux_builds: Vec<&'a str>,
+    /// `// compile-flags: <flags>`, already split into arguments
+    pub compile_flags: Vec<String>,
+    /// `// error-pattern:<text>`
+    pub error_patterns: Vec<&'a str>,
+    /// `// exec-panic: <text>`, only checked in `RunFail` mode
+    pub exec_panics: Vec<&'a str>,
+    /// `// exit-code: <n>`, only checked in `RunFail` mode
+    pub exit_code: Option<i32>,
+    /// `// ignore-test`
+    pub ignore: bool,
+    /// `// max-rustc: <version>`, the newest compiler the test applies to
+    ///
+    /// NB like `min_rustc`, this applies to the whole source file: all its revisions are ignored
+    /// with other compilers, annotations can't be restricted to some compiler versions.
+    pub max_rustc: Option<Version>,
+    /// `// min-rustc: <version>`, the oldest compiler the test applies to
+    pub min_rustc: Option<Version>,
+    /// `// check-pass`, `// build-pass`, `// run-pass` or `// run-fail`, `None` if none of them,
+    /// in which case the mode of the `Config` applies
+    pub mode: Option<Mode>,
+    /// `// normalize-stderr-test: "<regex>" -> "<replacement>"`
+    pub normalize_stderr: Vec<(Regex, String)>,
+    /// `// revisions: <name> <name> ...`
+    pub revisions: Vec<&'a str>,
+}
+
+impl<'a> Header<'a> {
+    /// Collects the header directives of this source file
+    pub fn parse(source: &'a str) -> Result<Header<'a>, (Span, Error<'a>)> {
+        let mut header = Header {
+            aux_builds: vec![],
+            compile_flags: vec![],
+            error_patterns: vec![],
+            exec_panics: vec![],
+            exit_code: None,
+            ignore: false,
+            max_rustc: None,
+            min_rustc: None,
+            mode: None,
+            normalize_stderr: vec![],
+            revisions: vec![],
+        };
+
+        let mut start_of_line = 0;
+        for line in source.lines() {
+            if let Some((name, value)) = directive(line) {
+                let mode = match name {
+                    "build-pass" => Some(Mode::BuildPass),
+                    "check-pass" => Some(Mode::CheckPass),
+                    "run-fail" => Some(Mode::RunFail),
+                    "run-pass" => Some(Mode::RunPass),
+                    _ => None,
+                };
+
+                if let Some(mode) = mode {
+                    if header.mode.map_or(false, |other| other != mode) {
+                        let span = Span(start_of_line, start_of_line + line.len());
+
+                        return Err((span, Error::ConflictingModes))
+                    }
+
+                    header.mode = Some(mode);
+                }
+
+                match name {
+                    "aux-build" => header.aux_builds.extend(value.map(str::trim)),
+                    "compile-flags" => if let Some(value) = value {
+                        // `value` is a suffix of `line`
+                        let offset = start_of_line + line.len() - value.len();
+
+                        match split(value) {
+                            Err((span, e)) => return Err((span + offset, e)),
+                            Ok(flags) => header.compile_flags.extend(flags),
+                        }
+                    },
+                    "error-pattern" => match value.map(str::trim) {
+                        Some(pattern) if !pattern.is_empty() => {
+                            header.error_patterns.push(pattern)
+                        },
+                        _ => {
+                            let span = Span(start_of_line, start_of_line + line.len());
+
+                            return Err((span, Error::EmptyErrorPattern))
+                        },
+                    },
+                    "exec-panic" => header.exec_panics.extend(value.map(str::trim)),
+                    "exit-code" => {
+                        let value = value.unwrap_or("");
+
+                        match value.trim().parse() {
+                            Ok(code) => header.exit_code = Some(code),
+                            Err(_) => {
+                                const NUMBER: &'static [Token] = &[Token::Number(0)];
+
+                                let code = value.trim();
+                                let start = start_of_line + line.len() - value.trim_left().len();
+                                let span = Span(start, start + code.len());
+
+                                return Err((span, Error::Expected(NUMBER)))
+                            },
+                        }
+                    },
+                    "ignore-test" => header.ignore = true,
+                    "max-rustc" | "min-rustc" => {
+                        let value = value.unwrap_or("");
+
+                        let version = match Version::parse(value) {
+                            Some(version) => version,
+                            None => {
+                                let version = value.trim();
+                                let start = start_of_line + line.len() - value.trim_left().len();
+                                let span = Span(start, start + version.len());
+
+                                return Err((span, Error::MalformedVersion(version)))
+                            },
+                        };
+
+                        if name == "max-rustc" {
+                            header.max_rustc = Some(version);
+                        } else {
+                            header.min_rustc = Some(version);
+                        }
+                    },
+                    "normalize-stderr-test" => {
+                        let value = value.unwrap_or("");
+                        let offset = start_of_line + line.len() - value.len();
+
+                        match normalization(value) {
+                            Err((span, e)) => return Err((span + offset, e)),
+                            Ok(rule) => header.normalize_stderr.push(rule),
+                        }
+                    },
+                    "revisions" => if let Some(value) = value {
+                        for revision in value.split_whitespace() {
+                            // NB `foo.run.stderr` is the snapshot of the test binary stderr
+                            if revision == "run" || revision.contains('.') {
+                                let start = start_of_line + line.len() - value.len() +
+                                    value.find(revision).unwrap();
+                                let span = Span(start, start + revision.len());
+
+                                return Err((span, Error::InvalidRevision(revision)))
+                            }
+
+                            header.revisions.push(revision);
+                        }
+                    },
+                    _ => {},
+                }
+            }
+
+            start_of_line += line.len() + "\n".len();
+        }
+
+        Ok(header)
+    }
+
+    /// Does the test apply to this compiler `version`?
+    ///
+    /// `// max-rustc: 1.20` includes all the `1.20.x` releases.
+    pub fn supports(&self, version: &Version) -> bool {
+        let too_old = self.min_rustc.map_or(false, |min| *version < min);
+        let too_new = self.max_rustc.map_or(false, |max| match max.patch {
+            None => (version.major, version.minor) > (max.major, max.minor),
+            Some(_) => *version > max,
+        });
+
+        !too_old && !too_new
+    }
+}
+
+/// Splits a `// <name>: <value>` line into its name and value
+fn directive(line: &str) -> Option<(&str, Option<&str>)> {
+    let line = line.trim_left();
+
+    if !line.starts_with("//") {
+        return None
+    }
+
+    let line = line["//".len()..].trim_left();
+    let end = line.find(|c: char| !c.is_alphanumeric() && c != '-').unwrap_or(line.len());
+    let (name, rest) = line.split_at(end);
+    let rest = rest.trim_left();
+
+    if rest.starts_with(":") {
+        Some((name, Some(&rest[1..])))
+    } else {
+        Some((name, None))
+    }
+}
+
+/// Parses a `"<regex>" -> "<replacement>"` normalization rule
+///
+/// The regex and the replacement are quoted like the arguments of `split`.
+fn normalization(value: &str) -> Result<(Regex, String), (Span, Error)> {
+    let span = Span(0, value.len());
+
+    match &try!(split(value))[..] {
+        [ref regex, ref arrow, ref replacement] if arrow == "->" => {
+            match source::regex(regex) {
+                Ok(regex) => Ok((regex, replacement.clone())),
+                Err(reason) => Err((span, Error::InvalidRegex(value.trim(), reason))),
+            }
+        },
+        _ => Err((span, Error::MalformedNormalization)),
+    }
+}
+
+/// Splits the `value` into arguments using shell-like rules
+///
+/// - Arguments are separated by whitespace
+/// - Single quotes preserve the literal value of every character in between
+/// - Double quotes preserve the literal value of every character in between, except that `\"` and
+///   `\\` are escapes
+/// - Outside quotes, a backslash preserves the literal value of the next character
+fn split(value: &str) -> Result<Vec<String>, (Span, Error<'static>)> {
+    fn quoted(
+        chars: &mut Peekable<CharIndices>,
+        start: BytePos,
+        quote: char,
+        arg: &mut String,
+    ) -> Result<(), (Span, Error<'static>)> {
+        while let Some((_, c)) = chars.next() {
+            match c {
+                c if c == quote => return Ok(()),
+                '\\' if quote == '"' => match chars.peek() {
+                    Some(&(_, c)) if c == '"' || c == '\\' => {
+                        arg.push(c);
+                        chars.next();
+                    },
+                    _ => arg.push('\\'),
+                },
+                c => arg.push(c),
+            }
+        }
+
+        Err((Span(start, start + 1), Error::UnterminatedQuote))
+    }
+
+    let mut args = vec![];
+    let mut arg = String::new();
+    // NB needed to tell apart an empty argument (`''`) from no argument
+    let mut in_arg = false;
+    let mut chars = value.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if in_arg {
+                    args.push(mem::replace(&mut arg, String::new()));
+                    in_arg = false;
+                }
+
+                continue
+            },
+            '\'' | '"' => try!(quoted(&mut chars, i, c, &mut arg)),
+            '\\' => match chars.next() {
+                Some((_, c)) => arg.push(c),
+                None => arg.push('\\'),
+            },
+            c => arg.push(c),
+        }
+
+        in_arg = true;
+    }
+
+    if in_arg {
+        args.push(arg);
+    }
+
+    Ok(args)
+}
+
+#[cfg(test)]
+mod test {
+    use rustc::Version;
+
+    use super::Header;
+
+    #[test]
+    fn compile_flags() {
+        let source = "\
+// compile-flags: -D warnings --cfg 'feature=\"x\"'
+// compile-flags:--crate-type lib -C \"link-args=-a \\\"b\\\"\" a\\ b
+fn main() {}
+";
+
+        let header = Header::parse(source).unwrap();
+
+        assert_eq!(header.compile_flags, vec![
+            "-D", "warnings", "--cfg", "feature=\"x\"", "--crate-type", "lib", "-C",
+            "link-args=-a \"b\"", "a b",
+        ]);
+    }
+
+    #[test]
+    fn error_patterns() {
+        let header = Header::parse("// error-pattern: mismatched types\n").unwrap();
+        assert_eq!(header.error_patterns, ["mismatched types"]);
+
+        assert!(Header::parse("// error-pattern:\n").is_err());
+        assert!(Header::parse("// error-pattern:  \n").is_err());
+    }
+
+    #[test]
+    fn normalize_stderr() {
+        let source = "\
+// normalize-stderr-test: \"(\\d+) previous errors\" -> \"$1 errors\"
+// normalize-stderr-test: \"[\" -> \"\"
+";
+
+        assert!(Header::parse(source).is_err());
+
+        let header = Header::parse(source.lines().next().unwrap()).unwrap();
+        let (ref regex, ref replacement) = header.normalize_stderr[0];
+
+        assert_eq!(regex.replace_all("aborting due to 2 previous errors", &replacement[..]),
+                   "aborting due to 2 errors");
+    }
+
+    #[test]
+    fn revisions() {
+        let header = Header::parse("// revisions: a b\n").unwrap();
+        assert_eq!(header.revisions, ["a", "b"]);
+
+        assert!(Header::parse("// revisions: a run\n").is_err());
+        assert!(Header::parse("// revisions: a.b\n").is_err());
+    }
+
+    #[test]
+    fn rustc_versions() {
+        let header = Header::parse("// min-rustc: 1.9\n// max-rustc: 1.20\n").unwrap();
+        let supports = |version| header.supports(&Version::parse(version).unwrap());
+
+        assert!(!supports("1.8.0"));
+        assert!(supports("1.9.0"));
+        assert!(supports("1.20.3"));
+        assert!(!supports("1.21.0"));
+        assert!(Header::parse("// min-rustc: 1.x\n").is_err());
+    }
+
+    #[test]
+    fn unterminated_quote() {
+        let source = "fn main() {}\n// compile-flags: --cfg 'foo\n";
+
+        assert!(Header::parse(source).is_err());
+    }
+}
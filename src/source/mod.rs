@@ -6,10 +6,14 @@ use std::io::{Read, self};
 use std::ops::Deref;
 use std::path::Path;
 
+use regex::Regex;
+
 use {Annotations, LineMap, Span};
 
+use self::header::Header;
 use self::parse::{Error, Parser};
 
+pub mod header;
 pub mod parse;
 
 /// The contents of a rust source file
@@ -32,17 +36,36 @@ impl Source {
         Ok(Source(contents))
     }
 
+    /// Collects the source file's header directives
+    pub fn header(&self) -> Result<Header, (Span, Error)> {
+        Header::parse(self)
+    }
+
     /// Parses the source file's annotations
-    pub fn parse(&self) -> Result<LineMap<Annotations>, (Span, Error)> {
+    ///
+    /// `revisions` are the revisions listed in the `// revisions` header. Only the annotations
+    /// that apply to `revision` are returned. If `revision` is `None`, only the annotations that
+    /// are not revision-specific are returned.
+    pub fn parse<'a>(
+        &'a self,
+        revisions: &[&'a str],
+        revision: Option<&str>,
+    ) -> Result<LineMap<Annotations<'a>>, (Span, Error<'a>)> {
         use std::collections::btree_map::Entry::{Occupied, Vacant};
 
         let source: &str = &self;
         let mut map: LineMap<Annotations> = BTreeMap::new();
 
-        let parser = Parser::new(source);
+        let parser = Parser::new(source, revisions);
 
         for lka in parser {
-            let (ln, kind, annotation) = try!(lka);
+            let (ln, kind, annotation, revisions) = try!(lka);
+
+            match (revision, revisions) {
+                (_, None) => {},
+                (Some(revision), Some(ref revisions)) if revisions.contains(&revision) => {},
+                _ => continue,
+            }
 
             match map.entry(ln) {
                 Occupied(mut entry) => {
@@ -60,6 +83,20 @@ impl Source {
     }
 }
 
+/// Compiles a regex found in the source file, on error returns only the reason why the regex is
+/// invalid
+///
+/// NB the errors of the `regex` crate also include a multi-line rendering of the pattern, which
+/// doesn't fit in a parse error.
+fn regex(pattern: &str) -> Result<Regex, String> {
+    Regex::new(pattern).map_err(|e| {
+        let e = e.to_string();
+        let reason = e.lines().last().unwrap_or("");
+
+        reason.trim_left_matches("error: ").to_string()
+    })
+}
+
 impl Deref for Source {
     type Target = str;
 
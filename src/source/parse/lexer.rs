@@ -10,14 +10,22 @@ use {BytePos, Kind, Span};
 /// Tokens found in `cfail` annotations
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Token {
+    /// `@`
+    At,
     /// `^`
     Caret,
     /// `:`
     Colon,
+    /// `-`
+    Dash,
     /// `error`
     Kind(Kind),
+    /// `12`
+    Number(u32),
     /// `|`
     Or,
+    /// `[a,b]`, a list of revisions or an error code
+    List,
     /// ` `
     Whitespace,
 }
@@ -25,10 +33,14 @@ pub enum Token {
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
+            Token::At => f.write_str("@"),
             Token::Caret => f.write_str("^"),
             Token::Colon => f.write_str(":"),
+            Token::Dash => f.write_str("-"),
             Token::Kind(..) => f.write_str("<kind>"),
+            Token::Number(..) => f.write_str("<number>"),
             Token::Or => f.write_str("|"),
+            Token::List => f.write_str("[<list>]"),
             Token::Whitespace => f.write_str(" "),
         }
     }
@@ -37,10 +49,15 @@ impl fmt::Display for Token {
 /// EBNF:
 ///
 /// ``` text
+/// at = "@" ;
 /// caret = "^" ;
 /// colon = ":" ;
+/// dash = "-" ;
 /// kind = "ERROR" | "HELP" | "NOTE" | "WARNING" | "error" | "help" | "note" | "warning" ;
+/// number = digit , { digit } ;
 /// or = "|" ;
+/// item = ( letter | digit | "_" | "-" ) , { letter | digit | "_" | "-" } ;
+/// list = "[" , item , { "," , item } , "]" ;
 /// whitespace = " " ;
 /// ```
 pub struct Lexer<'a> {
@@ -92,12 +109,65 @@ impl<'a> Iterator for Lexer<'a> {
                 let kind = match c {
                     ' ' => return spanned!(Ok(Token::Whitespace)),
                     ':' => return spanned!(Ok(Token::Colon)),
+                    '-' => return spanned!(Ok(Token::Dash)),
+                    '@' => return spanned!(Ok(Token::At)),
                     '^' => return spanned!(Ok(Token::Caret)),
                     '|' => return spanned!(Ok(Token::Or)),
+                    '[' => {
+                        let end = match self.input[i..].find(']') {
+                            Some(pos) => i + pos,
+                            None => self.input.len(),
+                        };
+
+                        let list = &self.input[i+1..end];
+                        let is_valid = end < self.input.len() && list.split(',').all(|item| {
+                            !item.is_empty() && item.chars().all(|c| {
+                                c.is_alphanumeric() || c == '_' || c == '-'
+                            })
+                        });
+
+                        if !is_valid {
+                            let span = Span(i + self.offset, end + self.offset);
+
+                            return (span, self.fatal(Error::MalformedList(list)))
+                        }
+
+                        // skip the items and the closing bracket
+                        while let Some(&(j, _)) = self.iter.peek() {
+                            self.iter.next();
+
+                            if j == end {
+                                break
+                            }
+                        }
+
+                        return spanned!(Ok(Token::List))
+                    },
                     'E' | 'e' => Kind::Error,
                     'H' | 'h' => Kind::Help,
                     'N' | 'n' => Kind::Note,
                     'W' | 'w' => Kind::Warning,
+                    c if c.is_digit(10) => {
+                        let mut end = self.input.len();
+
+                        while let Some(&(j, c)) = self.iter.peek() {
+                            if c.is_digit(10) {
+                                self.iter.next();
+                            } else {
+                                end = j;
+                                break;
+                            }
+                        }
+
+                        return match self.input[i..end].parse() {
+                            Ok(number) => spanned!(Ok(Token::Number(number))),
+                            Err(_) => {
+                                let span = Span(i + self.offset, end + self.offset);
+
+                                (span, self.fatal(Error::NumberTooLarge(&self.input[i..end])))
+                            },
+                        }
+                    },
                     c => {
                         return spanned!(self.fatal(Error::UnknownStartOfToken(c)))
                     },
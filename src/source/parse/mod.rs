@@ -1,6 +1,6 @@
 //! `cfail` annotation parser
 
-use std::borrow::{Cow, IntoCow};
+use std::borrow::IntoCow;
 use std::fmt;
 use std::iter::{Peekable, self};
 use std::path::Path;
@@ -8,23 +8,48 @@ use std::str::Lines;
 
 use unicode_width::UnicodeWidthStr;
 
-use {BytePos, Kind, Line, Span};
+use {Annotation, BytePos, Kind, Line, Location, Span};
+use source;
 
 use self::lexer::{Lexer, Token};
+use self::scanner::State;
 
 pub mod lexer;
+pub mod scanner;
 
 /// Parse errors
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub enum Error<'a> {
+    /// More than one of the `check-pass`, `build-pass`, `run-pass` and `run-fail` directives
+    ConflictingModes,
+    /// `// error-pattern` without a pattern, which would match any output
+    EmptyErrorPattern,
     /// Expected these tokens
     Expected(&'static [Token]),
+    /// `@<start>-<end>` annotation whose `end` column comes before its `start` column
+    InvalidColumnRange(u32, u32),
+    /// `/<regex>/` annotation with an invalid regex, and the reason why it's invalid
+    InvalidRegex(&'a str, String),
+    /// Revision whose snapshots would clash with other snapshots, see the `snapshot` module
+    InvalidRevision(&'a str),
     /// Used `//~^^^` with too many carets, and the adjusted line doesn't exist
     LineDoesntExist,
+    /// Malformed `[<list>]`
+    MalformedList(&'a str),
+    /// Malformed `// normalize-stderr-test` rule
+    MalformedNormalization,
+    /// Malformed compiler version
+    MalformedVersion(&'a str),
     /// Used `//~|`, but there is no annotation in the previous line
     NoPrecedingAnnotation,
+    /// Number doesn't fit in 32 bits
+    NumberTooLarge(&'a str),
     /// Unknown compiler message `kind`
     UnknownKind(&'a str),
+    /// A revision-specific annotation names a revision that's not listed in `// revisions`
+    UnknownRevision(&'a str),
+    /// A quote in a header directive was never closed
+    UnterminatedQuote,
     /// No token starts with this character
     UnknownStartOfToken(char),
 }
@@ -32,6 +57,8 @@ pub enum Error<'a> {
 impl<'a> fmt::Display for Error<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
+            Error::ConflictingModes => f.write_str("conflicting test modes"),
+            Error::EmptyErrorPattern => f.write_str("empty error pattern"),
             Error::Expected(toks) => {
                 match toks {
                     [tok] => write!(f, "expected token `{}`", tok),
@@ -53,9 +80,30 @@ impl<'a> fmt::Display for Error<'a> {
                     }
                 }
             },
+            Error::InvalidColumnRange(start, end) => {
+                write!(f, "invalid column range `{}-{}`, the span can't end before it starts",
+                       start,
+                       end)
+            },
+            Error::InvalidRegex(_, ref e) => write!(f, "invalid regex: {}", e),
+            Error::InvalidRevision(r) => {
+                write!(f, "invalid revision `{}`, it can't be `run` or contain a `.`", r)
+            },
             Error::LineDoesntExist => f.write_str("adjusted line doesn't exist"),
+            Error::MalformedList(l) => write!(f, "malformed list `[{}]`", l),
+            Error::MalformedNormalization => {
+                f.write_str("expected `\"<regex>\" -> \"<replacement>\"`")
+            },
+            Error::MalformedVersion(v) => {
+                write!(f, "malformed version `{}`, expected `<major>.<minor>[.<patch>]`", v)
+            },
             Error::NoPrecedingAnnotation => f.write_str("no annotation in previous line"),
+            Error::NumberTooLarge(n) => write!(f, "number `{}` is too large", n),
             Error::UnknownKind(k) => write!(f, "unknown kind `{}`", k),
+            Error::UnknownRevision(r) => {
+                write!(f, "unknown revision `{}`, it's not listed in `// revisions`", r)
+            },
+            Error::UnterminatedQuote => f.write_str("unterminated quote"),
             Error::UnknownStartOfToken(c) => write!(f, "unknown start of token `{}`", c),
         }
     }
@@ -110,6 +158,64 @@ pub fn format_error(path: &Path, source: &str, span: Span, e: Error) -> String {
     unreachable!();
 }
 
+/// Finds the start of an annotation, `//~` or `//[<revisions>]~`, in the line comment of this
+/// line. `state` is the state of the source scanner at the start of the line, and `declared` are
+/// the revisions of the source file.
+///
+/// Returns the byte position of the `//`, the byte position right after the `~`, and the
+/// revisions the annotation applies to
+fn find_start<'a>(
+    line: &'a str,
+    state: &mut State,
+    declared: &[&str],
+) -> Option<Result<(BytePos, BytePos, Revisions<'a>), (Span, Error<'a>)>> {
+    let mut from = match scanner::line_comment(line, state) {
+        None => return None,
+        Some(pos) => pos,
+    };
+
+    while let Some(pos) = line[from..].find("//") {
+        let pos = from + pos + "//".len();
+        let rest = &line[pos..];
+
+        if rest.starts_with("~") {
+            return Some(Ok((pos - "//".len(), pos + "~".len(), None)))
+        } else if rest.starts_with("[") && rest.contains("]~") {
+            match Lexer::new(rest, pos).next() {
+                Some((span, Ok(Token::List))) if line[span.1..].starts_with("~") => {
+                    let Span(start, end) = span;
+                    let mut revisions = vec![];
+
+                    let mut item = start + "[".len();
+                    for revision in line[start+1..end-1].split(',') {
+                        if !declared.contains(&revision) {
+                            let span = Span(item, item + revision.len());
+
+                            return Some(Err((span, Error::UnknownRevision(revision))))
+                        }
+
+                        revisions.push(revision);
+                        item += revision.len() + ",".len();
+                    }
+
+                    return Some(Ok((pos - "//".len(), end + "~".len(), Some(revisions))))
+                },
+                Some((span, Err(e))) => return Some(Err((span, e))),
+                _ => {},
+            }
+        }
+
+        from = pos;
+    }
+
+    None
+}
+
+/// Is this an error code like `E0308`?
+pub fn is_error_code(s: &str) -> bool {
+    s.len() == "E0000".len() && s.starts_with("E") && s[1..].chars().all(|c| c.is_digit(10))
+}
+
 /// A `cfail` annotation parser.
 ///
 /// Annotations can take any of the following forms:
@@ -145,23 +251,73 @@ pub fn format_error(path: &Path, source: &str, span: Span, e: Error) -> String {
 /// //~| <kind> <message>
 /// //~| <kind> <message>
 /// ```
+///
+/// - Column-precise annotations, the span of the compiler message must start at `<column>`, or
+///   cover exactly the columns from `<start>` to `<end>`. Columns start at 1, and can be combined
+///   with all the other forms.
+///
+/// ``` text
+/// let _: i8 = 0u8;
+/// //~^ <kind>@<column> <message>
+/// //~| <kind>@<start>-<end> <message>
+/// ```
+///
+///   NB the end of a span is only known if the span ends in the line where it starts, and if the
+///   compiler reports it, which the multi-line human readable format doesn't. Messages whose end
+///   is not known don't match `@<start>-<end>` annotations.
+///
+/// - Annotations that check the error code of the compiler message. The message can be omitted to
+///   only check the error code.
+///
+/// ``` text
+/// let _: i8 = 0u8;
+/// //~^ <kind>[<code>] <message>
+/// //~| <kind> <code>
+/// ```
+///
+/// - Regex annotations, the regex must match the compiler message. Regex annotations can't span
+///   several lines.
+///
+/// ``` text
+/// let _: i8 = 0u8;
+/// //~^ <kind> /<regex>/
+/// ```
+///
+/// - Revision-specific annotations, that only apply when the source file is compiled under one of
+///   the listed revisions. They can be combined with all the other forms.
+///
+/// ``` text
+/// let _: i8 = 0u8;
+/// //[a]~^ <kind> <message>
+/// //[b,c]~^^ <kind> <message>
+/// ```
+///
+/// Only line comments start annotations, `//~` inside string literals or block comments is not an
+/// annotation.
 pub struct Parser<'a> {
     curr_line: Line,
     last_line: Option<usize>,
     last_match: Option<Line>,
     lines: Peekable<Lines<'a>>,
+    /// Revisions listed in the `// revisions` header
+    revisions: Vec<&'a str>,
+    scanner: State,
     start_of_line: BytePos,
     state: Result<(), ()>,
 }
 
 impl<'a> Parser<'a> {
-    /// Creates a parser for this source file
-    pub fn new(source: &'a str) -> Parser<'a> {
+    /// Creates a parser for this source file, whose `// revisions` header lists `revisions`
+    ///
+    /// Revision-specific annotations that name any other revision are errors.
+    pub fn new(source: &'a str, revisions: &[&'a str]) -> Parser<'a> {
         Parser {
             curr_line: Line(0),
             last_line: None,
             last_match: None,
             lines: source.lines().peekable(),
+            revisions: revisions.to_vec(),
+            scanner: State::Code,
             start_of_line: 0,
             state: Ok(()),
         }
@@ -185,25 +341,36 @@ impl<'a> Parser<'a> {
     }
 }
 
+/// Revisions an annotation applies to, `None` means all of them
+pub type Revisions<'a> = Option<Vec<&'a str>>;
+
 impl<'a> Iterator for Parser<'a> {
-    type Item = Result<(Line, Kind, Cow<'a, str>), (Span, Error<'a>)>;
+    type Item = Result<(Line, Kind, Annotation<'a>, Revisions<'a>), (Span, Error<'a>)>;
 
-    fn next(&mut self) -> Option<Result<(Line, Kind, Cow<'a, str>), (Span, Error<'a>)>> {
+    fn next(
+        &mut self,
+    ) -> Option<Result<(Line, Kind, Annotation<'a>, Revisions<'a>), (Span, Error<'a>)>> {
         // Any kind
         const ANY: Kind = Kind::Error;
         const CARET_WS: &'static [Token] = &[Token::Caret, Token::Whitespace];
         const COLON_OR_WS: &'static [Token] = &[Token::Caret, Token::Or, Token::Whitespace];
         const COLON_WS: &'static [Token] = &[Token::Colon, Token::Whitespace];
         const K: &'static [Token] = &[Token::Kind(ANY)];
-        const START: &'static str = "//~";
+        const NUMBER: &'static [Token] = &[Token::Number(0)];
 
         if let Err(_) = self.state {
             return None
         }
 
         while let Some(line) = self.next_line() {
-            if let Some(pos) = line.find(START) {
-                let start = pos + START.len();
+            let start = match find_start(line, &mut self.scanner, &self.revisions) {
+                None => None,
+                Some(Err((span, e))) => return self.fatal(span, e),
+                Some(Ok(start)) => Some(start),
+            };
+
+            if let Some((comment, start, revisions)) = start {
+                let first = self.curr_line;
                 let mut lexer = Lexer::new(&line[start..], start).peekable();
 
                 let ln = match lexer.next() {
@@ -274,6 +441,61 @@ impl<'a> Iterator for Parser<'a> {
                     },
                 };
 
+                // optional `[<code>]`
+                let mut code = match lexer.peek() {
+                    Some(&(Span(start, end), Ok(Token::List))) => {
+                        lexer.next();
+
+                        let code = &line[start+1..end-1];
+                        if code.contains(',') {
+                            return self.fatal(Span(start, end), Error::MalformedList(code))
+                        }
+
+                        Some(code)
+                    },
+                    _ => None,
+                };
+
+                // optional `@<column>` or `@<start>-<end>`
+                let (column, last_column) = match lexer.peek() {
+                    Some(&(_, Ok(Token::At))) => {
+                        lexer.next();
+
+                        let number = |lexer: &mut Peekable<Lexer<'a>>| match lexer.next() {
+                            Some((span, Ok(Token::Number(number)))) => Ok((span, number)),
+                            Some((span, Err(e))) => Err((span, e)),
+                            Some((span, Ok(_))) => Err((span, Error::Expected(NUMBER))),
+                            None => Err((Span(line.len(), line.len()), Error::Expected(NUMBER))),
+                        };
+
+                        let (Span(start, _), column) = match number(&mut lexer) {
+                            Err((span, e)) => return self.fatal(span, e),
+                            Ok(number) => number,
+                        };
+
+                        match lexer.peek() {
+                            Some(&(_, Ok(Token::Dash))) => {
+                                lexer.next();
+
+                                let (Span(_, end), last) = match number(&mut lexer) {
+                                    Err((span, e)) => return self.fatal(span, e),
+                                    Ok(number) => number,
+                                };
+
+                                if last < column {
+                                    let e = Error::InvalidColumnRange(column, last);
+
+                                    return self.fatal(Span(start, end), e)
+                                }
+
+                                (Some(column), Some(last))
+                            },
+                            _ => (Some(column), None),
+                        }
+                    },
+                    _ => (None, None),
+                };
+
                 // optional `:`
                 match lexer.peek() {
                     Some(&(_, Ok(Token::Colon))) => {
@@ -300,13 +522,49 @@ impl<'a> Iterator for Parser<'a> {
 
                 let mut message = line[start..].into_cow();
 
-                // check if the message is multi-line
-                loop {
-                    if let Some(line) = self.lines.peek() {
-                        if let Some(pos) = line.find("//~|") {
+                // an annotation that only contains an error code, e.g. `//~ ERROR E0308`
+                if code.is_none() && is_error_code(line[start..].trim()) {
+                    code = Some(line[start..].trim());
+                    message = "".into_cow();
+                }
+
+                // a `/<regex>/` annotation
+                let pattern = line[start..].trim_right();
+                let regex = if pattern.len() >= 2 && pattern.starts_with("/") &&
+                    pattern.ends_with("/")
+                {
+                    let pattern = &pattern[1..pattern.len()-1];
+
+                    match source::regex(pattern) {
+                        Ok(regex) => Some(regex),
+                        Err(reason) => {
+                            let span = Span(start + 1, start + 1 + pattern.len());
+
+                            return self.fatal(span, Error::InvalidRegex(pattern, reason))
+                        },
+                    }
+                } else {
+                    None
+                };
+
+                // check if the message is multi-line, regexes can't span several lines
+                while regex.is_none() {
+                    if let Some(&line) = self.lines.peek() {
+                        let mut scanner = self.scanner;
+                        let start = match find_start(line, &mut scanner, &self.revisions) {
+                            Some(Ok((_, start, ref revs))) if *revs == revisions => {
+                                if line[start..].starts_with("|") {
+                                    Some(start + "|".len())
+                                } else {
+                                    None
+                                }
+                            },
+                            _ => None,
+                        };
+
+                        if let Some(start) = start {
                             const DUMMY: BytePos = 0;
 
-                            let start = pos + "//~|".len();
                             let line = line[start..].trim();
                             let mut lexer = Lexer::new(line, DUMMY);
 
@@ -316,6 +574,7 @@ impl<'a> Iterator for Parser<'a> {
                             } else {
                                 message.to_mut().push('\n');
                                 message.to_mut().push_str(line);
+                                self.scanner = scanner;
                             }
                         } else {
                             break
@@ -327,7 +586,20 @@ impl<'a> Iterator for Parser<'a> {
                     self.next_line();
                 }
 
-                return Some(Ok((ln, kind, message)))
+                let annotation = Annotation {
+                    code: code,
+                    column: column,
+                    last_column: last_column,
+                    location: Location {
+                        first: first,
+                        last: self.curr_line,
+                        start: comment,
+                    },
+                    message: message,
+                    regex: regex,
+                };
+
+                return Some(Ok((ln, kind, annotation, revisions)))
             } else {
                 self.last_match = None;
                 continue
@@ -337,3 +609,120 @@ impl<'a> Iterator for Parser<'a> {
         None
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use {Kind, Line};
+
+    use super::Parser;
+
+    #[test]
+    fn revisions() {
+        let source = "\
+let _: i8 = 0u8; //[a]~ ERROR mismatched types
+//[b,c]~^ ERROR mismatched
+//[b,c]~| expected `i8`
+";
+
+        let annotations = Parser::new(source, &["a", "b", "c"]);
+        let annotations = annotations.collect::<Result<Vec<_>, _>>().unwrap();
+
+        assert_eq!(annotations.len(), 2);
+        assert_eq!(annotations[0].0, Line(1));
+        assert_eq!(annotations[0].3, Some(vec!["a"]));
+        assert_eq!(annotations[1].0, Line(1));
+        assert_eq!(annotations[1].1, Kind::Error);
+        assert_eq!(annotations[1].2.message, "mismatched\nexpected `i8`");
+        assert_eq!(annotations[1].3, Some(vec!["b", "c"]));
+    }
+
+    #[test]
+    fn columns() {
+        let source = "\
+let _: i8 = 0u8;
+//~^ ERROR@13 mismatched types
+//~| ERROR@13-15: expected `i8`
+";
+
+        let annotations = Parser::new(source, &[]).collect::<Result<Vec<_>, _>>().unwrap();
+
+        assert_eq!((annotations[0].2.column, annotations[0].2.last_column), (Some(13), None));
+        assert_eq!(annotations[0].2.message, "mismatched types");
+        assert_eq!((annotations[1].2.column, annotations[1].2.last_column),
+                   (Some(13), Some(15)));
+        assert_eq!(annotations[1].2.message, "expected `i8`");
+
+        for source in &["//~ ERROR@ oops", "//~ ERROR@13- oops", "//~ ERROR@15-13 oops"] {
+            assert!(Parser::new(source, &[]).next().unwrap().is_err());
+        }
+    }
+
+    #[test]
+    fn error_codes() {
+        let source = "\
+let _: i8 = 0u8; //~ ERROR[E0308] mismatched types
+//~^ ERROR E0308
+";
+
+        let annotations = Parser::new(source, &[]).collect::<Result<Vec<_>, _>>().unwrap();
+
+        assert_eq!(annotations[0].2.code, Some("E0308"));
+        assert_eq!(annotations[0].2.message, "mismatched types");
+        assert_eq!(annotations[1].2.code, Some("E0308"));
+        assert_eq!(annotations[1].2.message, "");
+    }
+
+    #[test]
+    fn literals() {
+        let source = "\
+fn main() {
+    \"//~ not an annotation\";
+    /* //~ ERROR not an annotation either */
+    let _: i8 = 0u8; //~ ERROR mismatched types
+}
+";
+
+        let annotations = Parser::new(source, &[]).collect::<Result<Vec<_>, _>>().unwrap();
+
+        assert_eq!(annotations.len(), 1);
+        assert_eq!(annotations[0].0, Line(4));
+    }
+
+    #[test]
+    fn regexes() {
+        let source = "\
+let _: i8 = 0u8; //~ ERROR /mismatched types?/
+//~^ ERROR mismatched /types/
+";
+
+        let annotations = Parser::new(source, &[]).collect::<Result<Vec<_>, _>>().unwrap();
+
+        assert!(annotations[0].2.regex.as_ref().unwrap().is_match("mismatched type"));
+        assert!(annotations[1].2.regex.is_none());
+
+        let source = "let _: i8 = 0u8; //~ ERROR /(mismatched/\n";
+        let (span, e) = Parser::new(source, &[]).next().unwrap().unwrap_err();
+
+        assert_eq!(super::format_error(Path::new("a.rs"), source, span, e), "\
+a.rs:1:28: 1:39 error: invalid regex: unclosed group
+a.rs:1 let _: i8 = 0u8; //~ ERROR /(mismatched/
+                                   ^~~~~~~~~~~");
+    }
+
+    #[test]
+    fn unknown_revision() {
+        let source = "\
+// revisions: a b
+let _: i8 = 0u8; //[a,c]~ ERROR mismatched types
+";
+
+        let (span, e) = Parser::new(source, &["a", "b"]).next().unwrap().unwrap_err();
+
+        assert_eq!(super::format_error(Path::new("a.rs"), source, span, e), "\
+a.rs:2:22: 2:23 error: unknown revision `c`, it's not listed in `// revisions`
+a.rs:2 let _: i8 = 0u8; //[a,c]~ ERROR mismatched types
+                             ^");
+    }
+}
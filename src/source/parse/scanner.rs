@@ -0,0 +1,157 @@
+//! Rust source scanner
+//!
+//! Finds the line comments of a source file while skipping over string, raw string, byte string
+//! and char literals, and (nested) block comments.
+
+use BytePos;
+
+/// Scanner state, carried over from one line to the next
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum State {
+    /// Inside a block comment, with this nesting depth
+    BlockComment(u32),
+    /// Regular code
+    Code,
+    /// Inside a raw (byte) string literal delimited by this number of `#`s
+    RawStr(usize),
+    /// Inside a (byte) string literal
+    Str,
+}
+
+/// Scans a line of source code, and returns the byte position where its line comment starts
+///
+/// `state` is the state of the scanner at the start of the line, and will be updated to reflect
+/// the state at the end of the line.
+pub fn line_comment(line: &str, state: &mut State) -> Option<BytePos> {
+    let bytes = line.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let rest = &bytes[i..];
+
+        match *state {
+            State::BlockComment(depth) => {
+                if rest.starts_with(b"*/") {
+                    *state = if depth == 1 { State::Code } else { State::BlockComment(depth - 1) };
+                    i += 2;
+                } else if rest.starts_with(b"/*") {
+                    *state = State::BlockComment(depth + 1);
+                    i += 2;
+                } else {
+                    i += 1;
+                }
+            },
+            State::RawStr(hashes) => {
+                let is_closing = rest[0] == b'"' &&
+                    rest[1..].iter().take_while(|&&b| b == b'#').count() >= hashes;
+
+                if is_closing {
+                    *state = State::Code;
+                    i += 1 + hashes;
+                } else {
+                    i += 1;
+                }
+            },
+            State::Str => match rest[0] {
+                b'\\' => i += 2,
+                b'"' => {
+                    *state = State::Code;
+                    i += 1;
+                },
+                _ => i += 1,
+            },
+            State::Code => match rest[0] {
+                b'/' if rest.starts_with(b"//") => return Some(i),
+                b'/' if rest.starts_with(b"/*") => {
+                    *state = State::BlockComment(1);
+                    i += 2;
+                },
+                b'"' => {
+                    *state = State::Str;
+                    i += 1;
+                },
+                b'\'' => i += char_literal(&line[i..]).unwrap_or(1),
+                b'b' | b'r' if i == 0 || !is_ident(bytes[i - 1]) => {
+                    // length of the `r`/`br` prefix
+                    let start = if rest.starts_with(b"br") {
+                        2
+                    } else if rest[0] == b'r' {
+                        1
+                    } else {
+                        0
+                    };
+                    let hashes = rest[start..].iter().take_while(|&&b| b == b'#').count();
+
+                    if start != 0 && rest.get(start + hashes) == Some(&b'"') {
+                        *state = State::RawStr(hashes);
+                        i += start + hashes + 1;
+                    } else {
+                        i += 1;
+                    }
+                },
+                _ => i += 1,
+            },
+        }
+    }
+
+    None
+}
+
+/// If `input` starts with a char literal, returns its length. Returns `None` for lifetimes.
+fn char_literal(input: &str) -> Option<usize> {
+    let mut chars = input.char_indices().skip(1);
+
+    match chars.next() {
+        // escaped character, e.g. `'\n'`, `'\''` or `'\u{1F600}'`
+        Some((_, '\\')) => {
+            chars.next();
+            chars.find(|&(_, c)| c == '\'').map(|(i, _)| i + 1)
+        },
+        Some((_, _)) => match chars.next() {
+            Some((i, '\'')) => Some(i + 1),
+            _ => None,
+        },
+        None => None,
+    }
+}
+
+fn is_ident(byte: u8) -> bool {
+    byte == b'_' || (byte as char).is_alphanumeric()
+}
+
+#[cfg(test)]
+mod test {
+    use super::State;
+
+    fn line_comments(source: &str) -> Vec<Option<usize>> {
+        let mut state = State::Code;
+
+        source.lines().map(|line| super::line_comment(line, &mut state)).collect()
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(line_comments(r#"    "//~ not an annotation";"#), vec![None]);
+        assert_eq!(line_comments(r##"r#"//"#; b"\"//"; '"'; //"##), vec![Some(23)]);
+        assert_eq!(line_comments("fn f<'a>(x: &'a u8) {} // 'a'"), vec![Some(23)]);
+        assert_eq!(line_comments("let _ = ('\\'', '/'); // ok"), vec![Some(21)]);
+    }
+
+    #[test]
+    fn multi_line() {
+        let source = "\
+/* outer /* inner */ //
+   still a comment */ // 1
+let s = \"
+// in a string
+\"; // 4
+let s = r##\"
+\"# // still in a string
+\"##; // 7
+";
+
+        assert_eq!(line_comments(source), vec![
+            None, Some(22), None, None, Some(3), None, None, Some(5),
+        ]);
+    }
+}